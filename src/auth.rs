@@ -0,0 +1,601 @@
+use anyhow::{anyhow, Result};
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use axum::{
+    body::Body,
+    extract::{Query, Request, State},
+    http::{header, HeaderMap, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+    Form,
+};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use deadpool_postgres::Pool;
+use password_hash::{rand_core::OsRng, SaltString};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio_postgres::Row;
+
+use crate::signer::Signer;
+use crate::template::escape_html;
+use crate::AppState;
+
+// Users are stored in the database that sources query, in
+//
+//     CREATE TABLE webware_users (
+//         id serial PRIMARY KEY,
+//         username text UNIQUE NOT NULL,
+//         password_hash text NOT NULL,
+//         roles text[] NOT NULL DEFAULT '{}'
+//     );
+//
+// with password hashes from `webware hash-password`. Roles decide which
+// routes and sources a user can see.
+
+const SESSION_COOKIE: &str = "webware_session";
+
+// A hash with the default parameters that unknown usernames are checked
+// against, so that they take as long to reject as a wrong password.
+const DUMMY_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$BXohZc0Hzt3hjRzXWKT16w$wdrzWEViFCXFwMwBHR5VxcnoguQWCk5yhYBwDh8Hkh8";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub id: i32,
+    pub username: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+// A request for a route or source the user doesn't have a role for.
+#[derive(Debug)]
+pub struct Forbidden;
+
+impl std::fmt::Display for Forbidden {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Forbidden")
+    }
+}
+
+// Roles are listed separated by commas or spaces, as in `roles="a, b"`.
+pub fn parse_roles(roles: &str) -> Vec<String> {
+    roles
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|role| !role.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// Whether the user has one of the `required` roles. Nothing is required when
+// there are none, and without a user, nothing else is allowed.
+pub fn has_any_role(required: &[String], user: Option<&User>) -> bool {
+    required.is_empty()
+        || user.is_some_and(|user| user.roles.iter().any(|role| required.contains(role)))
+}
+
+// Requires a signed-in user for every page and source. Browsers sign in on
+// the login page and get a session cookie; machine clients send HTTP Basic
+// credentials, or a bearer token from `webware token`.
+#[derive(Clone)]
+pub struct Auth {
+    signer: Signer,
+    session_ttl: Duration,
+    secure_cookies: bool,
+}
+
+impl Auth {
+    pub fn new(signer: Signer) -> Self {
+        Auth {
+            signer,
+            session_ttl: Duration::from_secs(7 * 24 * 60 * 60),
+            secure_cookies: false,
+        }
+    }
+
+    pub fn session_ttl(mut self, session_ttl: Duration) -> Self {
+        self.session_ttl = session_ttl;
+        self
+    }
+
+    // Marks the session cookie `Secure`, for a server reached over HTTPS, so
+    // that browsers never send it over plain HTTP, like to a listener that
+    // redirects to HTTPS.
+    pub fn secure_cookies(mut self, secure_cookies: bool) -> Self {
+        self.secure_cookies = secure_cookies;
+        self
+    }
+
+    // The cookie attributes after the value, for setting or clearing it.
+    fn cookie_attributes(&self, base_path: &str, max_age: u64) -> String {
+        let secure = match self.secure_cookies {
+            true => "; Secure",
+            false => "",
+        };
+        format!(
+            "Path={}; Max-Age={}; HttpOnly; SameSite=Lax{}",
+            cookie_path(base_path),
+            max_age,
+            secure
+        )
+    }
+
+    pub(crate) fn signer(&self) -> &Signer {
+        &self.signer
+    }
+
+    // Session cookies and bearer tokens are the same signed user id. The
+    // rest of the user is looked up on each request, so that deleting a user
+    // or changing their roles takes effect before their tokens expire.
+    pub fn issue_token(&self, user: &User, ttl: Duration) -> String {
+        self.signer.sign(&user.id.to_string(), ttl)
+    }
+
+    async fn user_from_token(&self, client_pool: &Pool, token: &str) -> Result<Option<User>> {
+        let Some(id) = self.signer.verify(token).and_then(|id| id.parse().ok()) else {
+            return Ok(None);
+        };
+        find_user_by_id(client_pool, id).await
+    }
+}
+
+pub fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| anyhow!("Failed to hash password: {}", e))
+}
+
+pub async fn find_user(client_pool: &Pool, username: &str) -> Result<Option<(User, String)>> {
+    let client = client_pool.get().await?;
+    let row = client
+        .query_opt(
+            "SELECT id, username, roles, password_hash FROM webware_users WHERE username = $1",
+            &[&username],
+        )
+        .await?;
+    Ok(row.map(|row| (user_from_row(&row), row.get(3))))
+}
+
+async fn find_user_by_id(client_pool: &Pool, id: i32) -> Result<Option<User>> {
+    let client = client_pool.get().await?;
+    let row = client
+        .query_opt(
+            "SELECT id, username, roles FROM webware_users WHERE id = $1",
+            &[&id],
+        )
+        .await?;
+    Ok(row.as_ref().map(user_from_row))
+}
+
+fn user_from_row(row: &Row) -> User {
+    User {
+        id: row.get(0),
+        username: row.get(1),
+        roles: row.get(2),
+    }
+}
+
+// The user, if the password is theirs. Hashes are checked off the runtime,
+// since they are slow on purpose.
+pub async fn authenticate(
+    client_pool: &Pool,
+    username: &str,
+    password: &str,
+) -> Result<Option<User>> {
+    let (user, password_hash) = match find_user(client_pool, username).await? {
+        Some((user, password_hash)) => (Some(user), password_hash),
+        None => (None, DUMMY_HASH.to_string()),
+    };
+    let password = password.to_string();
+    let verified = tokio::task::spawn_blocking(move || {
+        PasswordHash::new(&password_hash)
+            .map(|hash| {
+                Argon2::default()
+                    .verify_password(password.as_bytes(), &hash)
+                    .is_ok()
+            })
+            .unwrap_or(false)
+    })
+    .await?;
+    Ok(user.filter(|_| verified))
+}
+
+// Paths that are served without a user: signing in, and the assets the login
+// page may load.
+fn is_public(path: &str) -> bool {
+    matches!(path, "/login" | "/logout" | "/index.js" | "/favicon.ico") || path.starts_with("/www/")
+}
+
+// Paths that machine clients and scripts request, which are rejected rather
+// than redirected to the login page.
+fn is_api(path: &str) -> bool {
+    path == "/api" || path.starts_with("/__webware/")
+}
+
+pub(crate) async fn require_user(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(auth) = &state.auth else {
+        return next.run(request).await;
+    };
+    let path = request.uri().path().to_string();
+    if is_public(&path) {
+        return next.run(request).await;
+    }
+    let headers = request.headers();
+    let has_credentials = headers.contains_key(header::AUTHORIZATION);
+    let user = match request_user(auth, &state, headers).await {
+        Ok(user) => user,
+        Err(e) => {
+            eprintln!("Authentication failed: {:#}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    match user {
+        Some(user) => {
+            request.extensions_mut().insert(user);
+            next.run(request).await
+        }
+        None if has_credentials || is_api(&path) => Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(header::WWW_AUTHENTICATE, r#"Bearer realm="webware""#)
+            .body(Body::empty())
+            .unwrap(),
+        None => {
+            let requested = request
+                .uri()
+                .path_and_query()
+                .map_or(path.as_str(), |p| p.as_str());
+            let next = format!("{}{}", state.base_path, requested);
+            let login = format!("{}/login?{}", state.base_path, query_pair("next", &next));
+            Redirect::to(&login).into_response()
+        }
+    }
+}
+
+async fn request_user(auth: &Auth, state: &AppState, headers: &HeaderMap) -> Result<Option<User>> {
+    if let Some(authorization) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+    {
+        if let Some(token) = authorization.strip_prefix("Bearer ") {
+            return auth.user_from_token(&state.client_pool, token.trim()).await;
+        }
+        if let Some(credentials) = authorization.strip_prefix("Basic ") {
+            let Some((username, password)) = STANDARD
+                .decode(credentials.trim())
+                .ok()
+                .and_then(|decoded| String::from_utf8(decoded).ok())
+                .and_then(|decoded| {
+                    decoded
+                        .split_once(':')
+                        .map(|(u, p)| (u.to_string(), p.to_string()))
+                })
+            else {
+                return Ok(None);
+            };
+            return authenticate(&state.client_pool, &username, &password).await;
+        }
+        return Ok(None);
+    }
+    match session_token(headers) {
+        Some(token) => auth.user_from_token(&state.client_pool, token).await,
+        None => Ok(None),
+    }
+}
+
+fn session_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .find_map(|cookie| {
+            let (name, value) = cookie.trim().split_once('=')?;
+            (name == SESSION_COOKIE).then_some(value)
+        })
+}
+
+fn query_pair(name: &str, value: &str) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            byte => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    format!("{}={}", name, encoded)
+}
+
+#[derive(Deserialize)]
+pub(crate) struct LoginQuery {
+    next: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct LoginForm {
+    username: String,
+    password: String,
+    next: Option<String>,
+}
+
+pub(crate) async fn login_page(
+    State(state): State<AppState>,
+    Query(query): Query<LoginQuery>,
+) -> Response {
+    let next = local_redirect(query.next.unwrap_or_default(), &state.base_path);
+    render_login(&state, StatusCode::OK, "", &next).await
+}
+
+pub(crate) async fn login(State(state): State<AppState>, Form(form): Form<LoginForm>) -> Response {
+    let Some(auth) = &state.auth else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let next = local_redirect(form.next.unwrap_or_default(), &state.base_path);
+    let user = match authenticate(&state.client_pool, &form.username, &form.password).await {
+        Ok(user) => user,
+        Err(e) => {
+            eprintln!("Login failed: {:#}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let Some(user) = user else {
+        let error = "Invalid username or password";
+        return render_login(&state, StatusCode::UNAUTHORIZED, error, &next).await;
+    };
+    let token = auth.issue_token(&user, auth.session_ttl);
+    let cookie = format!(
+        "{}={}; {}",
+        SESSION_COOKIE,
+        token,
+        auth.cookie_attributes(&state.base_path, auth.session_ttl.as_secs())
+    );
+    ([(header::SET_COOKIE, cookie)], Redirect::to(&next)).into_response()
+}
+
+// Only redirects within this site, so the login page can't be used to send
+// users elsewhere. Browsers drop tabs and newlines from URLs, which would make
+// `/\t/evil.com` another site's `//evil.com`, so whitespace isn't allowed.
+// The result is also a prop of the login page, which may paste it into a
+// script or a binding, so quotes, brackets and the like are percent-encoded.
+fn local_redirect(next: String, base_path: &str) -> String {
+    let is_local = next.starts_with('/')
+        && !next.starts_with("//")
+        && !next.contains('\\')
+        && !next.chars().any(|c| c.is_control() || c.is_whitespace())
+        && next
+            .parse::<Uri>()
+            .is_ok_and(|uri| uri.scheme().is_none() && uri.authority().is_none());
+    if !is_local {
+        return format!("{}/", base_path);
+    }
+    let mut escaped = String::new();
+    for byte in next.bytes() {
+        match byte.is_ascii_alphanumeric() || b"-._~/?#&=%+,;:@!$*".contains(&byte) {
+            true => escaped.push(byte as char),
+            false => escaped.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    escaped
+}
+
+pub(crate) async fn logout(State(state): State<AppState>) -> Response {
+    let Some(auth) = &state.auth else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let cookie = format!(
+        "{}=; {}",
+        SESSION_COOKIE,
+        auth.cookie_attributes(&state.base_path, 0)
+    );
+    let login = format!("{}/login", state.base_path);
+    ([(header::SET_COOKIE, cookie)], Redirect::to(&login)).into_response()
+}
+
+fn cookie_path(base_path: &str) -> &str {
+    match base_path.is_empty() {
+        true => "/",
+        false => base_path,
+    }
+}
+
+// A project can style its own login page as `login.html`, a template with the
+// props `action`, `next` and `error`, whose form posts `username`, `password`
+// and `next` to `action`.
+async fn render_login(state: &AppState, status: StatusCode, error: &str, next: &str) -> Response {
+    let action = format!("{}/login", state.base_path);
+    let templates = state.templates.read().await;
+    let html = match templates.contains("login.html") {
+        true => {
+            let props: BTreeMap<String, String> = [
+                ("action", &action),
+                ("next", &next.to_string()),
+                ("error", &error.to_string()),
+            ]
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+            match templates.build_template_page("login.html", &props) {
+                Ok(page) => page.with_base_path(&state.base_path).render(None),
+                Err(e) => {
+                    eprintln!("Failed to build login.html: {:#}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            }
+        }
+        false => default_login_page(&action, next, error),
+    };
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/html")
+        .body(Body::from(html))
+        .unwrap()
+}
+
+fn default_login_page(action: &str, next: &str, error: &str) -> String {
+    let error = match error.is_empty() {
+        true => String::new(),
+        false => format!(r#"<p style="color: #b91c1c">{}</p>"#, escape_html(error)),
+    };
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="UTF-8">
+  <meta name="viewport" content="width=device-width, initial-scale=1.0">
+  <title>Sign in</title>
+</head>
+<body style="font-family: sans-serif; max-width: 20rem; margin: 4rem auto">
+  <h1>Sign in</h1>
+  {}
+  <form method="post" action="{}">
+    <input type="hidden" name="next" value="{}">
+    <p><label>Username<br><input name="username" autocomplete="username" required autofocus></label></p>
+    <p><label>Password<br><input name="password" type="password" autocomplete="current-password" required></label></p>
+    <p><button type="submit">Sign in</button></p>
+  </form>
+</body>
+</html>
+"#,
+        error,
+        escape_html(action),
+        escape_html(next)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bundle::Bundle;
+    use crate::sql::StatementCollection;
+    use crate::template::TemplateCollection;
+    use crate::{Project, Webware};
+    use axum::http::Request;
+    use deadpool_postgres::{Config, Runtime};
+    use std::collections::HashMap;
+    use tokio_postgres::NoTls;
+    use tower::ServiceExt;
+
+    fn redirect(next: &str) -> String {
+        local_redirect(next.to_string(), "/app")
+    }
+
+    #[test]
+    fn local_redirect_keeps_paths_on_this_site() {
+        assert_eq!(redirect("/"), "/");
+        assert_eq!(
+            redirect("/app/reports?year=2024#top"),
+            "/app/reports?year=2024#top"
+        );
+        assert_eq!(redirect("/a%20b"), "/a%20b");
+    }
+
+    #[test]
+    fn local_redirect_rejects_other_sites() {
+        for next in [
+            "",
+            "reports",
+            "//evil.com",
+            "/\\evil.com",
+            "/\t/evil.com",
+            "/\n/evil.com",
+            "/ /evil.com",
+            "https://evil.com",
+            "javascript:alert(1)",
+        ] {
+            assert_eq!(redirect(next), "/app/", "{:?}", next);
+        }
+    }
+
+    #[test]
+    fn local_redirect_encodes_characters_scripts_care_about() {
+        assert_eq!(redirect("/x'+alert(1)+'"), "/x%27+alert%281%29+%27");
+        assert_eq!(redirect("/{{next}}"), "/%7B%7Bnext%7D%7D");
+    }
+
+    #[test]
+    fn cookie_attributes() {
+        let auth = Auth::new(Signer::random());
+        assert_eq!(
+            auth.cookie_attributes("", 60),
+            "Path=/; Max-Age=60; HttpOnly; SameSite=Lax"
+        );
+        let auth = auth.secure_cookies(true);
+        assert_eq!(
+            auth.cookie_attributes("/app", 0),
+            "Path=/app; Max-Age=0; HttpOnly; SameSite=Lax; Secure"
+        );
+    }
+
+    // A router that requires a user, with nothing to serve. The pool connects
+    // lazily, and no request here gets far enough to use it.
+    async fn router() -> axum::Router {
+        let templates = TemplateCollection::from_bundle(HashMap::new());
+        let statements = StatementCollection::from_bundle(HashMap::new());
+        let config = Config {
+            dbname: Some("webware".to_string()),
+            ..Default::default()
+        };
+        let pool = config.create_pool(Some(Runtime::Tokio1), NoTls).unwrap();
+        Webware::new(Project::new("."), pool)
+            .bundle(Bundle::new(&templates, &statements))
+            .auth(Auth::new(Signer::random()))
+            .router()
+            .await
+            .unwrap()
+    }
+
+    async fn get(path: &str, authorization: Option<&str>) -> Response {
+        let mut request = Request::get(path);
+        if let Some(authorization) = authorization {
+            request = request.header(header::AUTHORIZATION, authorization);
+        }
+        let request = request.body(Body::empty()).unwrap();
+        router().await.oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn require_user_redirects_browsers_to_the_login_page() {
+        let response = get("/reports?year=2024", None).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "/login?next=/reports%3Fyear%3D2024"
+        );
+    }
+
+    #[tokio::test]
+    async fn require_user_rejects_api_requests_and_bad_credentials() {
+        for (path, authorization) in [
+            ("/api?source=now.sql", None),
+            ("/__webware/reload", None),
+            ("/reports", Some("Bearer not-a-token")),
+            ("/reports", Some("Basic not-base64")),
+            ("/reports", Some("Digest username=ann")),
+        ] {
+            let response = get(path, authorization).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{}", path);
+            assert_eq!(
+                response.headers()[header::WWW_AUTHENTICATE],
+                r#"Bearer realm="webware""#
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn the_login_page_is_public() {
+        let response = get("/login?next=/x'", None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains(r#"name="next" value="/x%27""#), "{}", body);
+    }
+}
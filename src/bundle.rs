@@ -0,0 +1,58 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::sql::{Source, StatementCollection};
+use crate::template::{Template, TemplateCollection};
+
+// A project's templates and SQL, compiled ahead of time by `webware build` so
+// that a production server never reads the project's source files.
+#[derive(Serialize, Deserialize)]
+pub struct Bundle {
+    // The webware version that compiled the bundle. Compiled templates have
+    // no stable format, so a bundle only loads into the same version.
+    version: String,
+    templates: HashMap<String, Arc<Template>>,
+    statements: HashMap<String, Source>,
+}
+
+impl Bundle {
+    pub fn new(templates: &TemplateCollection, statements: &StatementCollection) -> Self {
+        Bundle {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            templates: templates.templates().clone(),
+            statements: statements.statements().clone(),
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string(self)?;
+        fs::write(path, json).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    pub fn read(path: &Path) -> Result<Bundle> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read bundle {}", path.display()))?;
+        let bundle: Bundle = serde_json::from_str(&json)
+            .with_context(|| format!("Invalid bundle {}", path.display()))?;
+        if bundle.version != env!("CARGO_PKG_VERSION") {
+            return Err(anyhow!(
+                "Bundle {} was built by webware {}, this is {}",
+                path.display(),
+                bundle.version,
+                env!("CARGO_PKG_VERSION")
+            ));
+        }
+        Ok(bundle)
+    }
+
+    pub fn into_collections(self) -> (TemplateCollection, StatementCollection) {
+        (
+            TemplateCollection::from_bundle(self.templates),
+            StatementCollection::from_bundle(self.statements),
+        )
+    }
+}
@@ -1,44 +1,151 @@
-use anyhow::{anyhow, Context, Result};
+use anyhow::{Context, Result};
 use fnv::FnvHasher;
 use rayon::prelude::*;
 use std::fs;
-use std::hash::{Hash, Hasher};
+use std::hash::Hasher;
 use std::path::Path;
 use std::time::UNIX_EPOCH;
 use std::{collections::HashMap, path::PathBuf};
 
-pub fn compute_cache_key(directory: &PathBuf) -> Result<u64> {
-    let entries: Vec<_> = fs::read_dir(directory)?
-        .map(|res| res.map(|e| e.path()))
-        .collect::<Result<Vec<_>, std::io::Error>>()?;
-
-    let mut fs_info: Vec<(u64, String)> = entries
-        .into_par_iter()
-        .map(|entry_path| {
-            let metadata = fs::metadata(&entry_path).context("Failed to get metadata")?;
-            let mtime = metadata
-                .modified()
-                .context("Failed to get mtime")?
-                .duration_since(UNIX_EPOCH)
-                .context("Invalid timestamp")?
-                .as_secs();
-            let relative_path = entry_path
-                .strip_prefix(&directory)
+// Dotfiles and the swap, backup and lock files editors leave next to the
+// files being edited.
+pub fn is_ignored(file_name: &str) -> bool {
+    file_name.starts_with('.')
+        || file_name.ends_with('~')
+        || file_name.ends_with(".swp")
+        || file_name.ends_with(".swo")
+        || file_name.ends_with(".swx")
+        || (file_name.starts_with('#') && file_name.ends_with('#'))
+}
+
+// Lists the files under `directory` recursively, keyed by their path relative
+// to it with `/` separators, e.g. `components/card.html`.
+pub fn list_files(directory: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let mut files = Vec::new();
+    let mut pending = vec![directory.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let entries = fs::read_dir(&dir)
+            .with_context(|| format!("Failed to read directory {}", dir.display()))?;
+        for entry in entries {
+            let entry = entry?;
+            if is_ignored(&entry.file_name().to_string_lossy()) {
+                continue;
+            }
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            let relative_path = path
+                .strip_prefix(directory)
                 .context("Failed to file path")?
-                .as_os_str()
-                .to_string_lossy()
-                .into_owned();
-            Ok((mtime, relative_path))
-        })
-        .collect::<Result<Vec<(u64, String)>>>()?;
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            files.push((relative_path, path));
+        }
+    }
+    Ok(files)
+}
+
+// Identifies a version of a file. Whole-second mtimes miss two saves within a
+// second, so the full mtime is used. With `hash_contents`, a hash of the
+// contents is used instead, which also catches files replaced with an older
+// mtime and filesystems whose mtimes are coarser still.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Fingerprint {
+    size: u64,
+    mtime: Option<u128>,
+    hash: Option<u64>,
+}
+
+fn fingerprint(path: &Path, hash_contents: bool) -> Result<Fingerprint> {
+    let metadata = fs::metadata(path).context("Failed to get metadata")?;
+    if hash_contents {
+        let mut hasher = FnvHasher::default();
+        hasher.write(&fs::read(path).context("Failed to read file")?);
+        return Ok(Fingerprint {
+            size: metadata.len(),
+            mtime: None,
+            hash: Some(hasher.finish()),
+        });
+    }
+    let mtime = metadata
+        .modified()
+        .context("Failed to get mtime")?
+        .duration_since(UNIX_EPOCH)
+        .context("Invalid timestamp")?
+        .as_nanos();
+    Ok(Fingerprint {
+        size: metadata.len(),
+        mtime: Some(mtime),
+        hash: None,
+    })
+}
+
+// The files in a directory as of the last committed compile.
+#[derive(Debug, Clone)]
+pub struct FileSet {
+    directory: PathBuf,
+    hash_contents: bool,
+    fingerprints: HashMap<String, Fingerprint>,
+}
+
+// The files added or changed, and removed, since the last commit.
+pub struct Changes {
+    pub changed: Vec<(String, PathBuf)>,
+    pub removed: Vec<String>,
+    fingerprints: HashMap<String, Fingerprint>,
+}
 
-    fs_info.par_sort_unstable_by_key(|k| k.0);
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.removed.is_empty()
+    }
+}
 
-    let mut hasher = FnvHasher::default();
-    for (mtime, path) in fs_info {
-        hasher.write_u64(mtime);
-        hasher.write(path.as_bytes());
+impl FileSet {
+    pub fn new(directory: PathBuf, hash_contents: bool) -> Self {
+        FileSet {
+            directory,
+            hash_contents,
+            fingerprints: HashMap::new(),
+        }
     }
 
-    Ok(hasher.finish())
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub fn changes(&self) -> Result<Changes> {
+        let fingerprints = list_files(&self.directory)?
+            .into_par_iter()
+            .map(|(name, path)| Ok((name, (fingerprint(&path, self.hash_contents)?, path))))
+            .collect::<Result<HashMap<_, _>>>()?;
+        let changed = fingerprints
+            .iter()
+            .filter(|(name, (fingerprint, _))| self.fingerprints.get(*name) != Some(fingerprint))
+            .map(|(name, (_, path))| (name.clone(), path.clone()))
+            .collect();
+        let removed = self
+            .fingerprints
+            .keys()
+            .filter(|name| !fingerprints.contains_key(*name))
+            .cloned()
+            .collect();
+        Ok(Changes {
+            changed,
+            removed,
+            fingerprints: fingerprints
+                .into_iter()
+                .map(|(name, (fingerprint, _))| (name, fingerprint))
+                .collect(),
+        })
+    }
+
+    // Records the changes as compiled, so they aren't reported again.
+    pub fn commit(&mut self, changes: Changes) {
+        self.fingerprints = changes.fingerprints;
+    }
 }
@@ -0,0 +1,207 @@
+use anyhow::Result;
+use deadpool_postgres::Pool;
+use serde_json::Value as Json;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+
+use crate::resource::ResourceName;
+use crate::sql::StatementCollection;
+use crate::template::TemplateCollection;
+
+// Checks what compiling alone can't: that every routed page builds, and that
+// every source a page reads has a SQL file. With a database, each statement
+// is also prepared, which checks its syntax and the tables and columns it
+// uses without running it. With `sample`, each source is also run, and the
+// fields its rows have are compared with the fields pages' bindings read.
+// Problems are printed as they're found, and counted in the result.
+pub async fn check(
+    templates: &TemplateCollection,
+    statements: &StatementCollection,
+    client_pool: Option<Arc<Pool>>,
+    sample: bool,
+) -> Result<usize> {
+    let mut problems = 0;
+    let mut report = |problem: String| {
+        eprintln!("{}", problem);
+        problems += 1;
+    };
+    // The pages that read each field of each source.
+    let mut reads: BTreeMap<ResourceName, BTreeMap<String, Vec<String>>> = BTreeMap::new();
+    for url in templates.routes()? {
+        match templates.build_page(url.clone()) {
+            Ok(page) => {
+                for source in page.sources() {
+                    if !statements.statements().contains_key(source.as_str()) {
+                        report(format!("{} reads {}, which has no SQL file", url, source));
+                    }
+                }
+                for (source, fields) in page.reads() {
+                    let source_reads = reads.entry(source.clone()).or_default();
+                    for field in fields {
+                        source_reads
+                            .entry(field.clone())
+                            .or_default()
+                            .push(url.clone());
+                    }
+                }
+            }
+            Err(e) => report(format!("{} doesn't build: {:#}", url, e)),
+        }
+    }
+    if let Some(client_pool) = client_pool {
+        let mut client = client_pool.get().await?;
+        let mut names: Vec<&String> = statements.statements().keys().collect();
+        names.sort();
+        let mut failed = HashSet::new();
+        for name in names {
+            for query in &statements.statements()[name].queries {
+                if let Err(e) = client.prepare(query).await {
+                    report(format!("{}: {}", name, db_message(&e)));
+                    failed.insert(name.as_str());
+                }
+            }
+        }
+        if sample {
+            for (source, fields) in &reads {
+                let Some(statement) = statements.statements().get(source.as_str()) else {
+                    continue;
+                };
+                if failed.contains(source.as_str()) {
+                    continue;
+                }
+                if !statement.params.is_empty() {
+                    eprintln!("Skipping {}, which takes a signed-in user's params", source);
+                    continue;
+                }
+                let mut rows = Vec::new();
+                let mut run_failed = false;
+                let transaction = client.build_transaction().read_only(true).start().await?;
+                for query in &statement.queries {
+                    let result = match transaction.query(query.as_str(), &[]).await {
+                        Ok(result) => result,
+                        Err(e) => {
+                            report(format!("{}: {}", source, db_message(&e)));
+                            run_failed = true;
+                            break;
+                        }
+                    };
+                    for row in result {
+                        match row.try_get::<_, Option<Json>>(0) {
+                            Ok(value) => rows.extend(value),
+                            Err(_) => {
+                                report(format!("{}: first column isn't JSON", source));
+                                run_failed = true;
+                                break;
+                            }
+                        }
+                    }
+                    if run_failed {
+                        break;
+                    }
+                }
+                transaction.rollback().await?;
+                if run_failed {
+                    continue;
+                }
+                if rows.is_empty() {
+                    eprintln!("Skipping {}, which returned no rows to compare", source);
+                    continue;
+                }
+                for (field, urls) in fields {
+                    if !produces(&rows, field) {
+                        report(format!(
+                            "{} reads {} from {}, whose rows don't have it",
+                            urls.join(", "),
+                            field,
+                            source
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    Ok(problems)
+}
+
+fn db_message(e: &tokio_postgres::Error) -> String {
+    match e.as_db_error() {
+        Some(db_error) => db_error.to_string(),
+        None => e.to_string(),
+    }
+}
+
+// Whether some row has the field at `path`, like `rows[].name` for the name
+// of the items in `rows`. Past a value that isn't an object, like `length` of
+// a string, fields can't be told apart from properties, so they count.
+fn produces(rows: &[Json], path: &str) -> bool {
+    let mut values: Vec<&Json> = rows.iter().collect();
+    for segment in path.split('.') {
+        let (field, items) = match segment.strip_suffix("[]") {
+            Some(field) => (field, true),
+            None => (segment, false),
+        };
+        if values.iter().any(|value| !value.is_object()) {
+            return true;
+        }
+        values = values.iter().filter_map(|value| value.get(field)).collect();
+        if values.is_empty() {
+            return false;
+        }
+        if items {
+            if values.iter().any(|value| !value.is_array()) {
+                return true;
+            }
+            values = values
+                .iter()
+                .filter_map(|value| value.as_array())
+                .flatten()
+                .collect();
+            // Without items, there's nothing to compare.
+            if values.is_empty() {
+                return true;
+            }
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn produces_fields_some_row_has() {
+        let rows = [json!({"name": "a"}), json!({"name": "b", "size": 2})];
+        assert!(produces(&rows, "name"));
+        assert!(produces(&rows, "size"));
+        assert!(!produces(&rows, "colour"));
+        assert!(!produces(&[], "name"));
+    }
+
+    #[test]
+    fn produces_nested_fields() {
+        let rows = [json!({"high": {"degrees": 21}})];
+        assert!(produces(&rows, "high.degrees"));
+        assert!(!produces(&rows, "high.unit"));
+        assert!(!produces(&rows, "low.degrees"));
+    }
+
+    #[test]
+    fn produces_fields_of_items() {
+        let rows = [json!({"rows": [{"name": "a"}, {"size": 2}]})];
+        assert!(produces(&rows, "rows[].name"));
+        assert!(produces(&rows, "rows[].size"));
+        assert!(!produces(&rows, "rows[].colour"));
+        // Empty lists, and values that aren't lists, can't be checked.
+        assert!(produces(&[json!({"rows": []})], "rows[].colour"));
+        assert!(produces(&[json!({"rows": "text"})], "rows[].colour"));
+    }
+
+    #[test]
+    fn produces_properties_of_values_that_arent_objects() {
+        let rows = [json!({"name": "a"})];
+        assert!(produces(&rows, "name.length"));
+        assert!(produces(&[json!("a")], "length"));
+    }
+}
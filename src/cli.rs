@@ -0,0 +1,150 @@
+use clap::{Args, Parser, Subcommand};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use webware::Project;
+
+#[derive(Parser)]
+#[command(
+    name = "webware",
+    version,
+    about = "Serves pages built from HTML templates and SQL"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Serve a project, or a bundle built from one
+    Serve(ServeArgs),
+    /// Compile every template and SQL file, and check the routed pages
+    Check(CheckArgs),
+    /// Compile a project into a bundle for production
+    Build(BuildArgs),
+    /// Write every routed page as a static site
+    Export(ExportArgs),
+    /// Create a new project
+    New {
+        /// The directory to create it in
+        directory: PathBuf,
+    },
+    /// Hash a password read from stdin, for the webware_users table
+    HashPassword,
+    /// Issue a bearer token for a user, for machine clients
+    Token(TokenArgs),
+}
+
+#[derive(Args)]
+pub struct SessionArgs {
+    /// The key that session cookies, bearer tokens and page tokens are signed
+    /// with, at least 32 bytes. Without one, they stop working when the
+    /// server restarts.
+    #[arg(long, env = "WEBWARE_SESSION_SECRET", hide_env_values = true)]
+    pub session_secret: Option<String>,
+}
+
+#[derive(Args)]
+pub struct ProjectArgs {
+    /// The project directory, containing src/templates, src/sql and www
+    #[arg(long, env = "WEBWARE_PROJECT", default_value = "project")]
+    pub project: PathBuf,
+}
+
+impl ProjectArgs {
+    pub fn project(&self) -> Project {
+        Project::new(&self.project)
+    }
+}
+
+#[derive(Args)]
+pub struct ServeArgs {
+    #[command(flatten)]
+    pub project: ProjectArgs,
+    /// The address to listen on
+    #[arg(long, env = "WEBWARE_BIND", default_value = "0.0.0.0:3000")]
+    pub bind: SocketAddr,
+    /// Rebuild when files change, and reload open pages
+    #[arg(long, env = "WEBWARE_DEV", conflicts_with = "bundle")]
+    pub dev: bool,
+    /// Render bound values on the server for the first paint
+    #[arg(long, env = "WEBWARE_SSR")]
+    pub ssr: bool,
+    /// Notice changes by file contents rather than modification times
+    #[arg(long, env = "WEBWARE_HASH_CONTENTS")]
+    pub hash_contents: bool,
+    /// The path the server is reached at behind a reverse proxy, like /dashboards
+    #[arg(long, env = "WEBWARE_BASE_PATH", default_value = "")]
+    pub base_path: String,
+    /// Send a strict Content-Security-Policy, with a nonce on the page's scripts
+    #[arg(long, env = "WEBWARE_CSP")]
+    pub csp: bool,
+    /// Serve HTTPS with this PEM certificate chain, reloaded when it changes
+    #[arg(long, env = "WEBWARE_TLS_CERT", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+    /// The PEM private key for --tls-cert
+    #[arg(long, env = "WEBWARE_TLS_KEY", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+    /// Also listen for plain HTTP on this address, redirecting it to HTTPS
+    #[arg(long, env = "WEBWARE_REDIRECT_HTTP", requires = "tls_cert")]
+    pub redirect_http: Option<SocketAddr>,
+    /// On SIGTERM or Ctrl-C, how many seconds running queries have to finish
+    /// before their pages are told to reconnect
+    #[arg(long, env = "WEBWARE_SHUTDOWN_GRACE", default_value_t = 10)]
+    pub shutdown_grace: u64,
+    /// Require users to sign in for every page and source
+    #[arg(long, env = "WEBWARE_AUTH")]
+    pub auth: bool,
+    #[command(flatten)]
+    pub session: SessionArgs,
+    /// Serve the templates and SQL of a bundle instead of the project's files
+    #[arg(long, env = "WEBWARE_BUNDLE")]
+    pub bundle: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct CheckArgs {
+    #[command(flatten)]
+    pub project: ProjectArgs,
+    /// Don't prepare the SQL statements against the database
+    #[arg(long)]
+    pub offline: bool,
+    /// Run each source in a read-only transaction, and report fields that
+    /// bindings read but its rows don't have
+    #[arg(long, conflicts_with = "offline")]
+    pub sample: bool,
+}
+
+#[derive(Args)]
+pub struct BuildArgs {
+    #[command(flatten)]
+    pub project: ProjectArgs,
+    /// Where to write the bundle
+    #[arg(short, long, default_value = "webware.bundle")]
+    pub out: PathBuf,
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    #[command(flatten)]
+    pub project: ProjectArgs,
+    /// The directory to write the site to
+    pub out: PathBuf,
+    /// Query each page's sources now and inline the data into the page
+    #[arg(long)]
+    pub snapshot: bool,
+    /// The path the site is hosted at, like /docs
+    #[arg(long, default_value = "")]
+    pub base_path: String,
+}
+
+#[derive(Args)]
+pub struct TokenArgs {
+    /// The user the token signs in as
+    pub username: String,
+    /// How long the token lasts
+    #[arg(long, default_value_t = 90)]
+    pub days: u64,
+    #[command(flatten)]
+    pub session: SessionArgs,
+}
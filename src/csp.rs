@@ -0,0 +1,115 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use fnv::FnvHasher;
+use password_hash::rand_core::{OsRng, RngCore};
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+// A fresh nonce for one response. Only scripts that carry it, and the modules
+// they import, run under the policy it is sent with.
+pub fn nonce() -> String {
+    let mut bytes = [0; 16];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+// Restricts scripts, which is what inline injection needs; styles, images and
+// connections are left to the project.
+pub fn policy(nonce: &str) -> String {
+    format!(
+        "script-src 'nonce-{}' 'strict-dynamic'; object-src 'none'; base-uri 'self'",
+        nonce
+    )
+}
+
+// How many binding modules are kept. A page's module only changes with its
+// templates, so this is far more than a project has pages; it bounds what
+// pages whose bindings differ by URL can add.
+const MAX_BINDING_MODULES: usize = 1024;
+
+// The binding modules of rendered pages, served so that pages don't need
+// inline code. They're named by a hash of their contents. When there are too
+// many, the one that was least recently rendered or fetched is dropped.
+#[derive(Clone, Default)]
+pub struct BindingModules {
+    modules: Arc<Mutex<Modules>>,
+}
+
+#[derive(Default)]
+struct Modules {
+    // Each module, with when it was last used.
+    by_name: HashMap<String, (Arc<str>, u64)>,
+    clock: u64,
+}
+
+impl Modules {
+    fn touch(&mut self, name: &str) -> Option<Arc<str>> {
+        self.clock += 1;
+        let (js, last_used) = self.by_name.get_mut(name)?;
+        *last_used = self.clock;
+        Some(js.clone())
+    }
+}
+
+impl BindingModules {
+    // Stores the module and returns the name it is served as.
+    pub async fn insert(&self, js: String) -> String {
+        let mut hasher = FnvHasher::default();
+        hasher.write(js.as_bytes());
+        let name = format!("{:016x}.js", hasher.finish());
+        let mut modules = self.modules.lock().await;
+        if modules.touch(&name).is_none() {
+            if modules.by_name.len() >= MAX_BINDING_MODULES {
+                let oldest = modules
+                    .by_name
+                    .iter()
+                    .min_by_key(|(_, (_, last_used))| *last_used)
+                    .map(|(name, _)| name.clone());
+                if let Some(oldest) = oldest {
+                    modules.by_name.remove(&oldest);
+                }
+            }
+            let clock = modules.clock;
+            modules.by_name.insert(name.clone(), (js.into(), clock));
+        }
+        name
+    }
+
+    pub async fn get(&self, name: &str) -> Option<Arc<str>> {
+        self.modules.lock().await.touch(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn binding_modules_drop_the_least_recently_used() {
+        let modules = BindingModules::default();
+        let first = modules.insert("first".to_string()).await;
+        let second = modules.insert("second".to_string()).await;
+        for i in 2..MAX_BINDING_MODULES {
+            modules.insert(i.to_string()).await;
+        }
+        // Fetching the first module makes the second the oldest.
+        assert_eq!(modules.get(&first).await.as_deref(), Some("first"));
+        modules.insert("one too many".to_string()).await;
+        assert_eq!(modules.get(&first).await.as_deref(), Some("first"));
+        assert_eq!(modules.get(&second).await, None);
+        assert_eq!(
+            modules.modules.lock().await.by_name.len(),
+            MAX_BINDING_MODULES
+        );
+    }
+
+    #[tokio::test]
+    async fn binding_modules_are_named_by_their_contents() {
+        let modules = BindingModules::default();
+        let name = modules.insert("same".to_string()).await;
+        assert_eq!(modules.insert("same".to_string()).await, name);
+        assert_ne!(modules.insert("different".to_string()).await, name);
+    }
+}
@@ -0,0 +1,156 @@
+use anyhow::{anyhow, Context, Result};
+use deadpool_postgres::Pool;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::auth::Forbidden;
+use crate::cache::list_files;
+use crate::resource::ResourceName;
+use crate::sql::{collect_sql_results, StatementCollection};
+use crate::template::TemplateCollection;
+use crate::INDEX_JS;
+
+// Writes every routed page as static HTML, along with the assets it loads,
+// so that the site can be served by any static host. Each page is written to
+// `<url>/index.html`, and links within the site are put under `base_path`.
+//
+// Without a snapshot, pages that read sources still open the event stream,
+// which a static host doesn't serve. With one, each page's source data is
+// queried now and inlined, so the page renders from it as it would with
+// server-side rendering.
+pub async fn export(
+    templates: &TemplateCollection,
+    statements: &StatementCollection,
+    client_pool: Arc<Pool>,
+    www_dir: &Path,
+    out: &Path,
+    base_path: &str,
+    snapshot: bool,
+) -> Result<()> {
+    let urls = templates.routes()?;
+    // Pages make their own directories, but every page may be left out.
+    fs::create_dir_all(out)
+        .with_context(|| format!("Failed to create directory {}", out.display()))?;
+    let mut failed = 0;
+    for url in &urls {
+        match export_page(
+            templates,
+            statements,
+            client_pool.clone(),
+            url,
+            out,
+            base_path,
+            snapshot,
+        )
+        .await
+        {
+            Ok(true) => println!("Exported {}", url),
+            Ok(false) => {}
+            Err(e) => {
+                eprintln!("Failed to export {}: {:#}", url, e);
+                failed += 1;
+            }
+        }
+    }
+    copy_assets(www_dir, &out.join("www"))?;
+    fs::write(out.join("index.js"), INDEX_JS)?;
+    let favicon = www_dir.join("images/favicon.ico");
+    if favicon.exists() {
+        fs::copy(favicon, out.join("favicon.ico"))?;
+    }
+    match failed {
+        0 => Ok(()),
+        failed => Err(anyhow!(
+            "Failed to export {} of {} pages",
+            failed,
+            urls.len()
+        )),
+    }
+}
+
+// Writes the page, or returns false when it has to be left out.
+async fn export_page(
+    templates: &TemplateCollection,
+    statements: &StatementCollection,
+    client_pool: Arc<Pool>,
+    url: &str,
+    out: &Path,
+    base_path: &str,
+    snapshot: bool,
+) -> Result<bool> {
+    let dir = match page_dir(url)? {
+        Some(dir) => out.join(dir.as_str()),
+        None => out.to_path_buf(),
+    };
+    let page = templates
+        .build_page(url.to_string())?
+        .with_base_path(base_path);
+    // A static host can't check who's reading, so pages with roles aren't
+    // written at all.
+    if page.is_restricted() {
+        eprintln!("Warning: skipping {}, which requires roles", url);
+        return Ok(false);
+    }
+    let sources = page.sources();
+    let html = match snapshot {
+        true => {
+            // Nor can it check who reads a snapshot, so sources with roles
+            // keep their page out too.
+            if let Err(e) = statements.authorize(&sources, None) {
+                if e.is::<Forbidden>() {
+                    eprintln!("Warning: skipping {}: {}", url, e.root_cause());
+                    return Ok(false);
+                }
+                return Err(e);
+            }
+            let dataset = collect_sql_results(client_pool, statements, sources, None).await?;
+            page.render(Some(&dataset))
+        }
+        false => {
+            if !sources.is_empty() {
+                eprintln!(
+                    "Warning: {} reads {}, which won't load from a static host without --snapshot",
+                    url,
+                    sources
+                        .iter()
+                        .map(ResourceName::as_str)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            }
+            page.render(None)
+        }
+    };
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+    fs::write(dir.join("index.html"), html)?;
+    Ok(true)
+}
+
+// The directory under the output a page's URL is written to, or None for the
+// root. URLs come from the project's routes, so their segments are checked
+// like file names, so that none of them can write outside the output.
+fn page_dir(url: &str) -> Result<Option<ResourceName>> {
+    let segments: Vec<&str> = url
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    match segments.is_empty() {
+        true => Ok(None),
+        false => ResourceName::parse(&segments.join("/"))
+            .map(Some)
+            .with_context(|| format!("Invalid route {:?}", url)),
+    }
+}
+
+fn copy_assets(from: &Path, to: &Path) -> Result<()> {
+    for (relative_path, path) in list_files(from)? {
+        let target = to.join(relative_path);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(&path, &target).with_context(|| format!("Failed to copy {}", path.display()))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use serde_json::Value as Json;
+
+// Evaluates the subset of binding expressions that can run on the server:
+// member paths rooted at the bound value (`data.high_temp.degrees`), string,
+// number and boolean literals, and template literals interpolating those.
+// Anything else returns None and is left for the browser.
+pub fn evaluate(expr: &str, param: &str, data: &Json) -> Option<Json> {
+    let expr = expr.trim();
+    if let Some(literal) = expr
+        .strip_prefix('`')
+        .and_then(|rest| rest.strip_suffix('`'))
+    {
+        return evaluate_template(literal, param, data).map(Json::String);
+    }
+    for quote in ['\'', '"'] {
+        if let Some(s) = expr
+            .strip_prefix(quote)
+            .and_then(|rest| rest.strip_suffix(quote))
+        {
+            return match s.contains(quote) || s.contains('\\') {
+                true => None,
+                false => Some(Json::String(s.to_string())),
+            };
+        }
+    }
+    match expr {
+        "true" => return Some(Json::Bool(true)),
+        "false" => return Some(Json::Bool(false)),
+        "null" => return Some(Json::Null),
+        _ => {}
+    }
+    if expr.starts_with(|c: char| c.is_ascii_digit()) {
+        return expr
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map(Json::Number);
+    }
+    evaluate_path(expr, param, data).cloned()
+}
+
+fn evaluate_path<'a>(expr: &str, param: &str, data: &'a Json) -> Option<&'a Json> {
+    let mut segments = expr.split('.');
+    if segments.next()? != param {
+        return None;
+    }
+    segments.try_fold(data, |value, segment| {
+        if !is_identifier(segment) {
+            return None;
+        }
+        value.as_object()?.get(segment)
+    })
+}
+
+fn evaluate_template(literal: &str, param: &str, data: &Json) -> Option<String> {
+    let mut result = String::new();
+    let mut rest = literal;
+    while let Some(start) = rest.find("${") {
+        let (text, tail) = rest.split_at(start);
+        let end = tail.find('}')?;
+        result.push_str(text);
+        result.push_str(&to_display(&evaluate(&tail[2..end], param, data)?)?);
+        rest = &tail[end + 1..];
+    }
+    if rest.contains(['`', '\\']) {
+        return None;
+    }
+    result.push_str(rest);
+    Some(result)
+}
+
+fn is_identifier(s: &str) -> bool {
+    !s.is_empty()
+        && !s.starts_with(|c: char| c.is_ascii_digit())
+        && s.chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+}
+
+// Formats a value the way JS would when assigning it to `textContent` or an
+// attribute. Objects and arrays don't have a useful string form, so they are
+// left for the browser.
+pub fn to_display(value: &Json) -> Option<String> {
+    match value {
+        Json::String(s) => Some(s.clone()),
+        Json::Bool(b) => Some(b.to_string()),
+        Json::Null => Some("null".to_string()),
+        Json::Number(n) => match n.as_f64() {
+            Some(f) if n.is_f64() && f.fract() == 0.0 && f.abs() < 1e15 => {
+                Some((f as i64).to_string())
+            }
+            _ => Some(n.to_string()),
+        },
+        Json::Array(_) | Json::Object(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn data() -> Json {
+        json!({
+            "name": "Oslo",
+            "high": {"degrees": 21, "unit": "C"},
+            "rain": 0.5,
+            "sunny": false,
+            "missing": null,
+            "days": [1, 2],
+        })
+    }
+
+    fn eval(expr: &str) -> Option<Json> {
+        evaluate(expr, "data", &data())
+    }
+
+    #[test]
+    fn evaluates_paths_on_the_param() {
+        assert_eq!(eval("data.name"), Some(json!("Oslo")));
+        assert_eq!(eval(" data.high.degrees "), Some(json!(21)));
+        assert_eq!(eval("data.days"), Some(json!([1, 2])));
+        assert_eq!(eval("data.nothing"), None);
+        assert_eq!(eval("data.name.length"), None);
+        assert_eq!(eval("row.name"), None);
+        assert_eq!(evaluate("row.name", "row", &data()), Some(json!("Oslo")));
+    }
+
+    #[test]
+    fn leaves_anything_else_for_the_browser() {
+        for expr in [
+            r#"data["name"]"#,
+            "data.days[0]",
+            "data.name.toUpperCase()",
+            "data.high.degrees + 1",
+            "data.sunny ? 'yes' : 'no'",
+            "'it''s'",
+            r"'a\nb'",
+            "`${data.name`",
+            "`${data.days}`",
+        ] {
+            assert_eq!(eval(expr), None, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn evaluates_literals() {
+        assert_eq!(eval("'hi'"), Some(json!("hi")));
+        assert_eq!(eval(r#""hi""#), Some(json!("hi")));
+        assert_eq!(eval("true"), Some(json!(true)));
+        assert_eq!(eval("null"), Some(Json::Null));
+        assert_eq!(eval("1.5"), Some(json!(1.5)));
+    }
+
+    #[test]
+    fn evaluates_template_literals() {
+        assert_eq!(
+            eval("`${data.name}: ${data.high.degrees}°${data.high.unit}`"),
+            Some(json!("Oslo: 21°C"))
+        );
+        assert_eq!(
+            eval("`${data.sunny} ${data.missing}`"),
+            Some(json!("false null"))
+        );
+        assert_eq!(eval("`plain`"), Some(json!("plain")));
+    }
+
+    #[test]
+    fn displays_values_like_js() {
+        assert_eq!(to_display(&json!("a")).as_deref(), Some("a"));
+        assert_eq!(to_display(&json!(21)).as_deref(), Some("21"));
+        assert_eq!(to_display(&json!(21.0)).as_deref(), Some("21"));
+        assert_eq!(to_display(&json!(0.5)).as_deref(), Some("0.5"));
+        assert_eq!(to_display(&json!(-3)).as_deref(), Some("-3"));
+        assert_eq!(to_display(&json!(true)).as_deref(), Some("true"));
+        assert_eq!(to_display(&Json::Null).as_deref(), Some("null"));
+        assert_eq!(to_display(&json!([1])), None);
+        assert_eq!(to_display(&json!({"a": 1})), None);
+    }
+}
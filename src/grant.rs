@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::auth::User;
+use crate::resource::ResourceName;
+use crate::signer::Signer;
+
+// What a rendered page may ask `/api` for: the sources it reads, for the user
+// it was rendered for. Pages carry it as a signed token, so the API only runs
+// queries some page exposed, and binds params for the user it exposed them to.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SourceGrant {
+    sources: Vec<ResourceName>,
+    user_id: Option<i32>,
+}
+
+impl SourceGrant {
+    pub fn new(mut sources: Vec<ResourceName>, user: Option<&User>) -> Self {
+        sources.sort();
+        SourceGrant {
+            sources,
+            user_id: user.map(|user| user.id),
+        }
+    }
+
+    pub fn issue(&self, signer: &Signer, ttl: Duration) -> String {
+        let payload = serde_json::to_string(self).expect("Grants serialize");
+        signer.sign(&payload, ttl)
+    }
+
+    pub fn verify(signer: &Signer, token: &str) -> Option<Self> {
+        serde_json::from_str(&signer.verify(token)?).ok()
+    }
+
+    pub fn allows(&self, sources: &[ResourceName], user: Option<&User>) -> bool {
+        self.user_id == user.map(|user| user.id)
+            && sources
+                .iter()
+                .all(|source| self.sources.binary_search(source).is_ok())
+    }
+}
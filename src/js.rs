@@ -0,0 +1,239 @@
+use oxc_allocator::Allocator;
+use oxc_ast::ast::{ComputedMemberExpression, Expression, StaticMemberExpression};
+use oxc_ast_visit::{walk, Visit};
+use oxc_parser::Parser;
+use oxc_span::{GetSpan, SourceType};
+use std::collections::BTreeSet;
+
+// A binding expression that isn't a single valid JS expression.
+#[derive(Debug)]
+pub struct ExpressionError {
+    pub expr: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Invalid expression `{}`: {}", self.expr, self.message)
+    }
+}
+
+impl std::error::Error for ExpressionError {}
+
+// Stands in for `{{name}}` placeholders while parsing, since props are only
+// substituted when a page is built.
+const PROP_PLACEHOLDER: &str = "__webware_prop";
+
+// Parses a binding expression, which becomes the body of an arrow function
+// taking `param`, and returns the fields it reads from `param`, like `name`
+// and `high.degrees` in `data.name + data["high"].degrees`.
+pub fn expression_fields(expr: &str, param: &str) -> Result<BTreeSet<String>, ExpressionError> {
+    parse(expr, param).map(|(fields, _)| fields)
+}
+
+// The fields the expression is made of alone, like `rows` for `data.rows`.
+pub fn expression_path(expr: &str, param: &str) -> Option<String> {
+    parse(expr, param).ok()?.1
+}
+
+fn parse(expr: &str, param: &str) -> Result<(BTreeSet<String>, Option<String>), ExpressionError> {
+    let error = |message: String| ExpressionError {
+        expr: expr.to_string(),
+        message,
+    };
+    let source = replace_props(expr);
+    // The body of `(data) => {...}` would be a block, not an object.
+    if source.trim_start().starts_with('{') {
+        return Err(error(
+            "object literals need parentheses, as in `({ a: 1 })`".to_string(),
+        ));
+    }
+    let allocator = Allocator::default();
+    let parsed = Parser::new(&allocator, &source, SourceType::mjs()).parse_expression();
+    let expression = match parsed {
+        Ok(expression) => expression,
+        Err(errors) => {
+            let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+            return Err(error(messages.join("; ")));
+        }
+    };
+    // The parser stops at the end of the first expression, as in `let` of
+    // `let x = 1`, so what follows it would be pasted into the page's code.
+    let end = expression.span().end as usize;
+    if end < source.trim_end().len() {
+        return Err(error(format!(
+            "unexpected `{}` after the expression",
+            source[end..].trim()
+        )));
+    }
+    if matches!(expression, Expression::SequenceExpression(_)) {
+        return Err(error(
+            "comma-separated expressions need parentheses, as in `(a, b)`".to_string(),
+        ));
+    }
+    let mut fields = FieldVisitor {
+        param,
+        fields: BTreeSet::new(),
+    };
+    let path = fields
+        .path(&expression)
+        .filter(|path| !path.is_empty())
+        .map(|path| path.join("."));
+    fields.visit_expression(&expression);
+    let fields = fields
+        .fields
+        .into_iter()
+        .filter(|field| !field.contains(PROP_PLACEHOLDER))
+        .collect();
+    Ok((fields, path.filter(|path| !path.contains(PROP_PLACEHOLDER))))
+}
+
+fn replace_props(expr: &str) -> String {
+    let mut result = String::new();
+    let mut rest = expr;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        result.push_str(&rest[..start]);
+        result.push_str(PROP_PLACEHOLDER);
+        rest = &rest[start + end + 2..];
+    }
+    result.push_str(rest);
+    result
+}
+
+struct FieldVisitor<'p> {
+    param: &'p str,
+    fields: BTreeSet<String>,
+}
+
+impl FieldVisitor<'_> {
+    // The fields `expr` goes through from the param, like `["a", "b"]` for
+    // `data.a["b"]`, if it is only that.
+    fn path(&self, expr: &Expression) -> Option<Vec<String>> {
+        match expr {
+            Expression::Identifier(id) if id.name == self.param => Some(Vec::new()),
+            Expression::StaticMemberExpression(member) => {
+                self.member_path(&member.object, &member.property.name)
+            }
+            Expression::ComputedMemberExpression(member) => match &member.expression {
+                Expression::StringLiteral(field) => self.member_path(&member.object, &field.value),
+                _ => None,
+            },
+            Expression::ParenthesizedExpression(inner) => self.path(&inner.expression),
+            _ => None,
+        }
+    }
+
+    fn member_path(&self, object: &Expression, field: &str) -> Option<Vec<String>> {
+        let mut path = self.path(object)?;
+        path.push(field.to_string());
+        Some(path)
+    }
+}
+
+// Records the longest path of each member chain on the param, so
+// `data.a.b.length` reads `a.b.length`, and leaves it to whoever checks the
+// path to know that `length` is a property of whatever `b` is.
+impl<'a> Visit<'a> for FieldVisitor<'_> {
+    fn visit_static_member_expression(&mut self, it: &StaticMemberExpression<'a>) {
+        match self.member_path(&it.object, &it.property.name) {
+            Some(path) => {
+                self.fields.insert(path.join("."));
+            }
+            None => walk::walk_static_member_expression(self, it),
+        }
+    }
+
+    fn visit_computed_member_expression(&mut self, it: &ComputedMemberExpression<'a>) {
+        let path = match &it.expression {
+            Expression::StringLiteral(field) => self.member_path(&it.object, &field.value),
+            _ => None,
+        };
+        match path {
+            Some(path) => {
+                self.fields.insert(path.join("."));
+            }
+            None => walk::walk_computed_member_expression(self, it),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(expr: &str, param: &str) -> Vec<String> {
+        expression_fields(expr, param)
+            .unwrap()
+            .into_iter()
+            .collect()
+    }
+
+    fn rejection(expr: &str) -> String {
+        match expression_fields(expr, "data") {
+            Ok(fields) => panic!("`{}` was accepted, reading {:?}", expr, fields),
+            Err(e) => e.message,
+        }
+    }
+
+    #[test]
+    fn rejects_code_after_the_expression() {
+        assert!(rejection("data.a // comment").contains("after the expression"));
+        assert!(rejection("data.a; alert(1)").contains("after the expression"));
+        assert!(rejection("let x = 1").contains("x = 1"));
+    }
+
+    #[test]
+    fn rejects_unparenthesized_sequences() {
+        assert!(rejection("data.a, alert(1)").contains("comma-separated"));
+        assert_eq!(fields("(data.a, data.b)", "data"), ["a", "b"]);
+    }
+
+    #[test]
+    fn rejects_leading_braces() {
+        assert!(rejection("{ a: 1 }").contains("object literals"));
+        assert!(rejection("  {}").contains("object literals"));
+        assert!(expression_fields("({ a: data.a })", "data").is_ok());
+    }
+
+    #[test]
+    fn parses_prop_placeholders() {
+        assert_eq!(fields("data[{{field}}] + data.b", "data"), ["b"]);
+        assert_eq!(fields("{{prefix}} + data.a", "data"), ["a"]);
+        assert_eq!(expression_path("data.{{field}}", "data"), None);
+    }
+
+    #[test]
+    fn reads_member_chains_on_the_param() {
+        assert_eq!(fields(r#"data["a"].b"#, "data"), ["a.b"]);
+        assert_eq!(fields("data.a.b.length", "data"), ["a.b.length"]);
+        assert_eq!(fields("data[key].b", "data"), Vec::<String>::new());
+        assert_eq!(fields("data.a + other.b", "data"), ["a"]);
+        assert_eq!(fields("f(data.a, data.b.c)", "data"), ["a", "b.c"]);
+    }
+
+    #[test]
+    fn reads_fields_of_the_row_in_a_row_scope() {
+        assert_eq!(fields("row.name + data.total", "row"), ["name"]);
+        assert_eq!(
+            fields(r#"`${row["first"]} ${row.last}`"#, "row"),
+            ["first", "last"]
+        );
+    }
+
+    #[test]
+    fn paths_are_only_whole_member_chains() {
+        assert_eq!(
+            expression_path(r#"data["a"].b"#, "data").as_deref(),
+            Some("a.b")
+        );
+        assert_eq!(
+            expression_path("(data.rows)", "data").as_deref(),
+            Some("rows")
+        );
+        assert_eq!(expression_path("data.rows.slice(1)", "data"), None);
+        assert_eq!(expression_path("data", "data"), None);
+    }
+}
@@ -0,0 +1,562 @@
+use anyhow::{anyhow, Context, Result};
+
+use axum::{
+    body::Body,
+    extract::Query,
+    extract::State,
+    http::StatusCode,
+    http::Uri,
+    middleware,
+    response::{IntoResponse, Redirect, Response},
+    routing::get,
+    Extension, Router,
+};
+use axum_macros::debug_handler;
+use futures::{stream, StreamExt};
+use std::convert::Infallible;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc::unbounded_channel, RwLock};
+use tower_http::services::ServeDir;
+pub mod auth;
+pub mod bundle;
+mod cache;
+pub mod check;
+mod csp;
+pub mod export;
+mod expr;
+mod grant;
+mod js;
+pub mod resource;
+pub mod scaffold;
+pub mod shutdown;
+pub mod signer;
+pub mod sql;
+pub mod template;
+pub mod tls;
+mod watch;
+use deadpool_postgres::Pool;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+use auth::{Auth, Forbidden, User};
+use bundle::Bundle;
+use csp::BindingModules;
+use grant::SourceGrant;
+use resource::ResourceName;
+use shutdown::Shutdown;
+use signer::Signer;
+use sql::{collect_sql_results, send_sql_results, StatementCollection};
+use template::TemplateCollection;
+use watch::{BuildErrors, Reload};
+
+// How long a page can take to open its event stream after it is rendered.
+const SOURCES_TOKEN_TTL: Duration = Duration::from_secs(10 * 60);
+
+// Sent instead of `stream_stop` when shutdown cuts off a page's sources, so
+// that it reloads once the server is back, with a token the server takes,
+// rather than keep what arrived.
+const RECONNECT_EVENT: &str = "event: reconnect\ndata: \n\n";
+
+// How soon pages reconnect to the reload stream after the server shuts down.
+const RELOAD_RETRY: &str = "retry: 1000\n\n";
+
+// The framework's client runtime, served as /index.js.
+pub const INDEX_JS: &str = include_str!("../www/index.js");
+
+// An error in the project's own files, as opposed to the environment it runs
+// in, so that callers can tell a broken deploy from a broken host.
+#[derive(Debug)]
+pub struct ProjectError;
+
+impl std::fmt::Display for ProjectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "The project has errors")
+    }
+}
+
+// A project directory, with its templates in src/templates, its SQL in
+// src/sql, and its static assets in www.
+#[derive(Debug, Clone)]
+pub struct Project {
+    root: PathBuf,
+}
+
+impl Project {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Project { root: root.into() }
+    }
+
+    pub fn template_dir(&self) -> PathBuf {
+        self.root.join("src/templates")
+    }
+
+    pub fn sql_dir(&self) -> PathBuf {
+        self.root.join("src/sql")
+    }
+
+    pub fn www_dir(&self) -> PathBuf {
+        self.root.join("www")
+    }
+
+    // Compiles every template and SQL file, failing on the first that doesn't.
+    pub async fn compile(
+        &self,
+        client_pool: Arc<Pool>,
+    ) -> Result<(TemplateCollection, StatementCollection)> {
+        let mut templates = TemplateCollection::new(self.template_dir(), false);
+        let mut statements = StatementCollection::new(self.sql_dir(), false);
+        templates.recompile().context(ProjectError)?;
+        statements
+            .recompile(client_pool)
+            .await
+            .context(ProjectError)?;
+        Ok((templates, statements))
+    }
+}
+
+// `dashboards/` and `/dashboards` are both `/dashboards`, and `/` is empty.
+pub fn normalize_base_path(base_path: &str) -> String {
+    let trimmed = base_path.trim_matches('/');
+    match trimmed.is_empty() {
+        true => String::new(),
+        false => format!("/{}", trimmed),
+    }
+}
+
+// Takes the base path off a request path that still carries it, as `/dash/`
+// does: axum's nesting only strips it from `/dash` and `/dash/...`.
+fn strip_base_path<'a>(path: &'a str, base_path: &str) -> &'a str {
+    match path.strip_prefix(base_path) {
+        Some(rest) if !base_path.is_empty() && (rest.is_empty() || rest.starts_with('/')) => rest,
+        _ => path,
+    }
+}
+
+#[derive(Clone)]
+struct AppState {
+    client_pool: Arc<Pool>,
+    templates: Arc<RwLock<TemplateCollection>>,
+    statements: Arc<RwLock<StatementCollection>>,
+    // Render bound values on the server for the first paint.
+    ssr: bool,
+    // Push reloads to open pages when files change.
+    dev: bool,
+    // The path the router is reached at, e.g. `/dashboards`, or empty at `/`.
+    base_path: String,
+    // Requires a signed-in user for pages and sources, when set.
+    auth: Option<Auth>,
+    // Signs the tokens that list the sources a page may read.
+    signer: Signer,
+    // Send a Content Security Policy, with pages' bindings loaded from
+    // `binding_modules` rather than inline.
+    csp: bool,
+    binding_modules: BindingModules,
+    reloads: broadcast::Sender<Reload>,
+    build_errors: Arc<RwLock<BuildErrors>>,
+    // Ends event streams when the server shuts down.
+    shutdown: Shutdown,
+}
+
+// Builds the router that serves a project, with sources queried from `pool`,
+// to run on its own or to nest in another axum service:
+//
+//     let dashboards = Webware::new(Project::new("dashboards"), pool)
+//         .base_path("/dashboards")
+//         .router()
+//         .await?;
+//     let app = Router::new().nest("/dashboards", dashboards);
+pub struct Webware {
+    project: Project,
+    pool: Pool,
+    bundle: Option<Bundle>,
+    dev: bool,
+    ssr: bool,
+    hash_contents: bool,
+    base_path: String,
+    auth: Option<Auth>,
+    signer: Option<Signer>,
+    csp: bool,
+    shutdown: Shutdown,
+}
+
+impl Webware {
+    pub fn new(project: Project, pool: Pool) -> Self {
+        Webware {
+            project,
+            pool,
+            bundle: None,
+            dev: false,
+            ssr: false,
+            hash_contents: false,
+            base_path: String::new(),
+            auth: None,
+            signer: None,
+            csp: false,
+            shutdown: Shutdown::default(),
+        }
+    }
+
+    // Serves the templates and SQL of a bundle instead of the project's
+    // files. A bundle is served exactly as built: nothing is watched, and
+    // development mode is ignored.
+    pub fn bundle(mut self, bundle: Bundle) -> Self {
+        self.bundle = Some(bundle);
+        self
+    }
+
+    // Rebuilds when files change, reloads open pages, and shows build errors
+    // in place of pages.
+    pub fn dev(mut self, dev: bool) -> Self {
+        self.dev = dev;
+        self
+    }
+
+    // Renders bound values on the server for the first paint.
+    pub fn ssr(mut self, ssr: bool) -> Self {
+        self.ssr = ssr;
+        self
+    }
+
+    // Notices changes by file contents, for filesystems whose mtimes are too
+    // coarse to notice every change.
+    pub fn hash_contents(mut self, hash_contents: bool) -> Self {
+        self.hash_contents = hash_contents;
+        self
+    }
+
+    // The path the router is reached at from the browser, like `/dashboards`
+    // when a reverse proxy or an enclosing router serves it there. It is put
+    // in front of the URLs pages link to, and taken off request paths that
+    // still carry it.
+    pub fn base_path(mut self, base_path: &str) -> Self {
+        self.base_path = normalize_base_path(base_path);
+        self
+    }
+
+    // Requires a signed-in user for every page and source.
+    pub fn auth(mut self, auth: Auth) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    // Signs the tokens pages are given to read their sources, which every
+    // server behind a load balancer needs to share. Without one, the auth
+    // signer is used, or else a key that lasts until restart.
+    pub fn signer(mut self, signer: Signer) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    // Sends a strict Content Security Policy with every page: only scripts
+    // with the response's nonce run, and pages load their bindings as a
+    // module instead of inline code.
+    pub fn csp(mut self, csp: bool) -> Self {
+        self.csp = csp;
+        self
+    }
+
+    // Ends the router's event streams when `shutdown` begins, so that a
+    // graceful shutdown of the server serving it isn't held up by them.
+    pub fn shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    // Compiles the project and returns its router. Outside of development, a
+    // file that fails to compile is a `ProjectError`.
+    pub async fn router(self) -> Result<Router> {
+        let bundled = self.bundle.is_some();
+        let (templates, statements) = match self.bundle {
+            Some(bundle) => bundle.into_collections(),
+            None => (
+                TemplateCollection::new(self.project.template_dir(), self.hash_contents),
+                StatementCollection::new(self.project.sql_dir(), self.hash_contents),
+            ),
+        };
+        let state = AppState {
+            client_pool: Arc::new(self.pool),
+            templates: Arc::new(RwLock::new(templates)),
+            statements: Arc::new(RwLock::new(statements)),
+            ssr: self.ssr,
+            dev: self.dev && !bundled,
+            base_path: self.base_path.clone(),
+            signer: self
+                .signer
+                .or_else(|| self.auth.as_ref().map(|auth| auth.signer().clone()))
+                .unwrap_or_else(Signer::random),
+            auth: self.auth,
+            csp: self.csp,
+            binding_modules: BindingModules::default(),
+            reloads: broadcast::channel(16).0,
+            build_errors: Arc::new(RwLock::new(BuildErrors::default())),
+            shutdown: self.shutdown,
+        };
+        if !bundled {
+            // In development, a broken file shouldn't keep the server from
+            // starting; it shows on the error page until it is fixed.
+            let templates_built = watch::rebuild_templates(&state).await;
+            let statements_built = watch::rebuild_statements(&state).await;
+            if !state.dev {
+                templates_built.context(ProjectError)?;
+                statements_built.context(ProjectError)?;
+            }
+            watch::watch(state.clone()).await?;
+        }
+
+        let favicon = format!("{}/www/images/favicon.ico", self.base_path);
+        let mut app = Router::new();
+        if state.dev {
+            app = app.route("/__webware/reload", get(reload_events));
+        }
+        if state.csp {
+            app = app.route("/__webware/bindings/:name", get(binding_module));
+        }
+        if state.auth.is_some() {
+            app = app
+                .route("/login", get(auth::login_page).post(auth::login))
+                .route("/logout", get(auth::logout).post(auth::logout));
+        }
+        let app = app
+            .nest_service("/www", ServeDir::new(self.project.www_dir()))
+            .route("/api", get(stream_sql_response))
+            .route(
+                "/index.js",
+                get(|| async { ([("Content-Type", "text/javascript")], INDEX_JS) }),
+            )
+            .route(
+                "/favicon.ico",
+                get(|| async move { Redirect::permanent(&favicon) }),
+            )
+            .fallback(get(template_response))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                auth::require_user,
+            ))
+            .with_state(state);
+        // Reverse proxies and enclosing routers may strip the base path from
+        // requests or pass it on, so serve both.
+        Ok(match self.base_path.is_empty() {
+            true => app,
+            false => Router::new()
+                .nest(&self.base_path, app.clone())
+                .fallback_service(app),
+        })
+    }
+}
+
+#[debug_handler]
+async fn template_response(
+    uri: Uri,
+    State(state): State<AppState>,
+    user: Option<Extension<User>>,
+) -> Response {
+    if state.dev {
+        if let Some(error) = state.build_errors.read().await.message() {
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .header("Content-Type", "text/html")
+                .body(Body::from(template::error_page(&error, &state.base_path)))
+                .unwrap();
+        }
+    }
+    let user = user.map(|Extension(user)| user);
+    // The development error page's own script runs without a policy.
+    let nonce = state.csp.then(csp::nonce);
+    match render_page(uri, &state, user.as_ref(), nonce.as_deref()).await {
+        Ok(response) => {
+            let mut builder = Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "text/html");
+            if let Some(nonce) = &nonce {
+                builder = builder.header("Content-Security-Policy", csp::policy(nonce));
+            }
+            builder.body(Body::from(response)).unwrap()
+        }
+        Err(e) if e.is::<Forbidden>() => Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body(Body::from(e.to_string()))
+            .unwrap(),
+        Err(e) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from(e.to_string()))
+            .unwrap(),
+    }
+}
+
+async fn render_page(
+    uri: Uri,
+    state: &AppState,
+    user: Option<&User>,
+    nonce: Option<&str>,
+) -> Result<String> {
+    let mut page = state
+        .templates
+        .read()
+        .await
+        .build_page(strip_base_path(uri.path(), &state.base_path).to_string())?
+        .with_live_reload(state.dev)
+        .with_base_path(&state.base_path);
+    if !page.allows(user) {
+        return Err(anyhow!("Not allowed to see {}", uri.path())).context(Forbidden);
+    }
+    // Open pages re-run their sources with the same token when SQL files
+    // change, so in development it lasts the day.
+    let ttl = match state.dev {
+        true => Duration::from_secs(24 * 60 * 60),
+        false => SOURCES_TOKEN_TTL,
+    };
+    let token = SourceGrant::new(page.sources(), user).issue(&state.signer, ttl);
+    page = page.with_sources_token(token);
+    if let Some(nonce) = nonce {
+        let name = state.binding_modules.insert(page.bindings_module()).await;
+        page = page
+            .with_nonce(nonce)
+            .with_bindings_url(format!("/__webware/bindings/{}", name));
+    }
+    if !state.ssr {
+        return Ok(page.render(None));
+    }
+    let statements = state.statements.read().await;
+    // Sources the user can't read are left for the browser, which is refused
+    // them by the event stream.
+    let sources = page
+        .sources()
+        .into_iter()
+        .filter(|source| {
+            statements
+                .authorize(std::slice::from_ref(source), user)
+                .is_ok()
+        })
+        .collect();
+    match collect_sql_results(state.client_pool.clone(), &statements, sources, user).await {
+        Ok(dataset) => Ok(page.render(Some(&dataset))),
+        // The page still works without the first paint: the browser queries
+        // the sources itself, and shows their errors as it would without SSR.
+        Err(e) => {
+            eprintln!("Failed to render {} on the server: {:#}", uri.path(), e);
+            Ok(page.render(None))
+        }
+    }
+}
+
+#[debug_handler]
+async fn binding_module(
+    State(state): State<AppState>,
+    axum::extract::Path(name): axum::extract::Path<String>,
+) -> Response {
+    match state.binding_modules.get(&name).await {
+        // Named by their contents, so they never change.
+        Some(js) => (
+            [
+                ("Content-Type", "text/javascript"),
+                ("Cache-Control", "public, max-age=31536000, immutable"),
+            ],
+            js.to_string(),
+        )
+            .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+#[debug_handler]
+async fn stream_sql_response(
+    State(state): State<AppState>,
+    user: Option<Extension<User>>,
+    Query(params): Query<Vec<(String, String)>>,
+) -> Response {
+    let sources = params
+        .iter()
+        .filter_map(|(key, value)| match key.as_str() {
+            "source" => Some(ResourceName::parse(value)),
+            _ => None,
+        })
+        .collect::<Result<Vec<_>>>();
+    let sources = match sources {
+        Ok(sources) => sources,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+
+    let user = user.map(|Extension(user)| user);
+    let grant = params
+        .iter()
+        .find(|(key, _)| key == "token")
+        .and_then(|(_, token)| SourceGrant::verify(&state.signer, token));
+    if !grant.is_some_and(|grant| grant.allows(&sources, user.as_ref())) {
+        return (
+            StatusCode::FORBIDDEN,
+            "Sources can only be read with a token from the page that reads them",
+        )
+            .into_response();
+    }
+    // The stream runs from a copy of its sources, so that it doesn't hold up
+    // a rebuild, and a rebuild can't take sources away from it.
+    let statements = {
+        let statements = state.statements.read().await;
+        if let Err(e) = statements.authorize(&sources, user.as_ref()) {
+            let status = match e.is::<Forbidden>() {
+                true => StatusCode::FORBIDDEN,
+                false => StatusCode::NOT_FOUND,
+            };
+            return (status, format!("{:#}", e)).into_response();
+        }
+        statements.snapshot(&sources)
+    };
+
+    let (tx, rx) = unbounded_channel::<Result<String, anyhow::Error>>();
+
+    tokio::spawn(async move {
+        let user = user.as_ref();
+        let sent = send_sql_results(state.client_pool, &statements, sources, user, tx.clone());
+        tokio::select! {
+            result = sent => match result {
+                Ok(_) => match tx.send(Ok("event: stream_stop\ndata: \n\n".to_string())) {
+                    Ok(_) => tokio::time::sleep(tokio::time::Duration::from_millis(100)).await,
+                    Err(e) => eprintln!("Final message send failed {}", e),
+                },
+                Err(e) => eprintln!("SQL result send failed: {e}"),
+            },
+            _ = state.shutdown.deadline() => {
+                let _ = tx.send(Ok(RECONNECT_EVENT.to_string()));
+            }
+        }
+    });
+
+    let rx_stream = UnboundedReceiverStream::new(rx);
+    let body = Body::from_stream(rx_stream);
+    (
+        StatusCode::OK,
+        [
+            ("Content-Type", "text/event-stream"),
+            ("x-custom", "custom"),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+#[debug_handler]
+async fn reload_events(State(state): State<AppState>) -> impl IntoResponse {
+    let reloads = stream::unfold(state.reloads.subscribe(), |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(reload) => {
+                    let event = format!("event: {}\ndata: \n\n", reload.event_name());
+                    return Some((Ok::<_, Infallible>(event), rx));
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+    // Pages reconnect to a stream that ends, which lets the server shut down.
+    let shutdown = state.shutdown.clone();
+    let reloads = reloads
+        .take_until(async move { shutdown.begun().await })
+        .chain(stream::once(async { Ok(RELOAD_RETRY.to_string()) }));
+    (
+        StatusCode::OK,
+        [("Content-Type", "text/event-stream")],
+        Body::from_stream(reloads),
+    )
+}
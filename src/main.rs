@@ -1,158 +1,225 @@
-#![allow(dead_code)]
-#![allow(unused_variables)]
-#![allow(unused_imports)]
-use anyhow::Result;
-
-use axum::{
-    body::Body,
-    extract::Query,
-    extract::State,
-    http::StatusCode,
-    http::Uri,
-    response::{Html, IntoResponse, Redirect, Response},
-    routing::get,
-    Error as AxumError, Router,
-};
-use axum_macros::debug_handler;
-use futures::stream::select;
-use futures::Stream;
-use futures::TryStreamExt;
-use futures::{stream, StreamExt};
-use serde_json::Value as Json;
-use std::collections::HashMap;
-use std::convert::Infallible;
-use std::fs;
-use std::path::{Path, PathBuf};
-use std::pin::Pin;
+use anyhow::{anyhow, Context, Result};
+use axum_server::Handle;
+use clap::Parser;
+use std::net::SocketAddr;
+use std::process::ExitCode;
 use std::sync::Arc;
-use std::time::Instant;
-use tokio::sync::{
-    mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
-    RwLock,
-};
-use tokio_postgres::{Client, NoTls};
-use tower_http::services::{ServeDir, ServeFile};
+use std::time::Duration;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
 use tower_http::trace::TraceLayer;
-mod cache;
-mod sql;
-mod template;
-use deadpool_postgres::Pool;
-use tokio_stream::wrappers::UnboundedReceiverStream;
+mod cli;
 
-use sql::{create_pool, send_sql_results, StatementCollection};
-use template::TemplateCollection;
+use cli::{BuildArgs, CheckArgs, Cli, Command, ExportArgs, ServeArgs, SessionArgs, TokenArgs};
+use webware::auth::{self, Auth};
+use webware::bundle::Bundle;
+use webware::shutdown::Shutdown;
+use webware::signer::Signer;
+use webware::sql::create_pool;
+use webware::{check, export, normalize_base_path, scaffold, tls, ProjectError, Webware};
 
-#[derive(Clone)]
-pub struct AppState {
-    client_pool: Arc<Pool>,
-    templates: Arc<RwLock<TemplateCollection>>,
-    statements: Arc<RwLock<StatementCollection>>,
-}
+// Exit codes: 1 when the project has errors, 2 for invalid arguments (from
+// clap), and 3 for any other failure, like a port in use or an unreadable
+// bundle.
+const EXIT_PROJECT_ERROR: u8 = 1;
+const EXIT_FAILURE: u8 = 3;
+
+// How long past the grace period a shutdown waits for connections to send
+// their last events.
+const SHUTDOWN_FLUSH: Duration = Duration::from_secs(1);
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
     tracing_subscriber::fmt::init();
 
-    let client_pool = create_pool().await?;
-    let state = AppState {
-        client_pool: Arc::new(client_pool),
-        templates: Arc::new(RwLock::new(TemplateCollection::new(PathBuf::from(
-            "project/src/templates",
-        )))),
-        statements: Arc::new(RwLock::new(StatementCollection::new(PathBuf::from(
-            "project/src/sql",
-        )))),
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Serve(args) => serve(args).await,
+        Command::Check(args) => check(args).await,
+        Command::Build(args) => build(args).await,
+        Command::Export(args) => export(args).await,
+        Command::New { directory } => scaffold::new_project(&directory),
+        Command::HashPassword => hash_password(),
+        Command::Token(args) => token(args).await,
     };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            match e.downcast_ref::<ProjectError>() {
+                Some(_) => ExitCode::from(EXIT_PROJECT_ERROR),
+                None => ExitCode::from(EXIT_FAILURE),
+            }
+        }
+    }
+}
 
-    // Set up the router and routes
-    let app = Router::new()
-        .nest_service("/www", ServeDir::new("project/www"))
-        .route("/api", get(stream_sql_response))
-        .route_service("/index.js", ServeFile::new("www/index.js"))
-        .route(
-            "/favicon.ico",
-            get(|| async { Redirect::permanent("/www/images/favicon.ico") }),
-        )
-        .fallback(get(template_response))
-        .layer(TraceLayer::new_for_http())
-        .with_state(state);
+fn signer(args: &SessionArgs) -> Result<Signer> {
+    match &args.session_secret {
+        Some(secret) => Signer::new(secret.as_bytes()),
+        None => {
+            eprintln!("Warning: no session secret is set, so sessions end on restart");
+            Ok(Signer::random())
+        }
+    }
+}
 
-    // Run the application
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+fn hash_password() -> Result<()> {
+    let mut password = String::new();
+    std::io::stdin().read_line(&mut password)?;
+    let password = password.trim_end_matches(['\r', '\n']);
+    if password.is_empty() {
+        return Err(anyhow!("No password given on stdin"));
+    }
+    println!("{}", auth::hash_password(password)?);
+    Ok(())
+}
 
+async fn token(args: TokenArgs) -> Result<()> {
+    if args.session.session_secret.is_none() {
+        return Err(anyhow!("Tokens need the server's session secret"));
+    }
+    let auth = Auth::new(signer(&args.session)?);
+    let client_pool = create_pool().await?;
+    let (user, _) = auth::find_user(&client_pool, &args.username)
+        .await?
+        .ok_or_else(|| anyhow!("No user named {}", args.username))?;
+    let ttl = Duration::from_secs(args.days * 24 * 60 * 60);
+    println!("{}", auth.issue_token(&user, ttl));
     Ok(())
 }
 
-#[debug_handler]
-async fn template_response(uri: Uri, State(state): State<AppState>) -> Response {
-    {
-        if state.templates.read().await.check() {
-            let mut templates_w = state.templates.write().await;
-            templates_w.recompile().unwrap();
+async fn serve(args: ServeArgs) -> Result<()> {
+    let client_pool = create_pool().await?;
+    let shutdown = Shutdown::new(Duration::from_secs(args.shutdown_grace));
+    let mut webware = Webware::new(args.project.project(), client_pool.clone())
+        .shutdown(shutdown.clone())
+        .dev(args.dev)
+        .ssr(args.ssr)
+        .hash_contents(args.hash_contents)
+        .base_path(&args.base_path)
+        .csp(args.csp);
+    if args.auth {
+        let auth = Auth::new(signer(&args.session)?).secure_cookies(args.tls_cert.is_some());
+        webware = webware.auth(auth);
+    } else if let Some(secret) = &args.session.session_secret {
+        webware = webware.signer(Signer::new(secret.as_bytes())?);
+    }
+    if let Some(path) = &args.bundle {
+        webware = webware.bundle(Bundle::read(path)?);
+    }
+    let app = webware.router().await?.layer(TraceLayer::new_for_http());
+
+    // Binding before serving reports a port in use here, rather than from
+    // inside the server.
+    let listener = bind(args.bind)?;
+    let handle = Handle::new();
+    tokio::spawn(shut_down_on_signal(handle.clone(), shutdown));
+    match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => {
+            let config = tls::rustls_config(cert, key).await?;
+            if let Some(addr) = args.redirect_http {
+                let redirect = axum_server::from_tcp(bind(addr)?)
+                    .handle(handle.clone())
+                    .serve(tls::redirect_router(args.bind.port()).into_make_service());
+                println!("Redirecting http://{} to HTTPS", addr);
+                tokio::spawn(async move {
+                    if let Err(e) = redirect.await {
+                        eprintln!("HTTP redirect listener failed: {}", e);
+                    }
+                });
+            }
+            println!("Listening on https://{}", args.bind);
+            axum_server::from_tcp_rustls(listener, config)
+                .handle(handle)
+                .serve(app.into_make_service())
+                .await?;
+        }
+        _ => {
+            println!("Listening on http://{}", args.bind);
+            axum_server::from_tcp(listener)
+                .handle(handle)
+                .serve(app.into_make_service())
+                .await?;
         }
     }
-    let templates = state.templates.read().await;
-    match templates.get_page(uri.to_string()) {
-        Ok(response) => Response::builder()
-            .status(StatusCode::OK)
-            .header("Content-Type", "text/html")
-            .body(Body::from(response))
-            .unwrap(),
-        Err(e) => Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .body(Body::from(e.to_string()))
-            .unwrap(),
+    // Connections are closed by now, so every client is back in the pool.
+    client_pool.close();
+    println!("Shut down");
+    Ok(())
+}
+
+// Stops accepting connections on SIGTERM or Ctrl-C, and gives open ones the
+// grace period to finish, plus a moment for the events that end them to be
+// sent. Connections still open after that are closed.
+async fn shut_down_on_signal(handle: Handle, shutdown: Shutdown) {
+    let terminate = async {
+        #[cfg(unix)]
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                eprintln!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+        #[cfg(not(unix))]
+        std::future::pending::<()>().await;
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate => {}
     }
+    println!("Shutting down");
+    shutdown.begin();
+    handle.graceful_shutdown(Some(shutdown.grace() + SHUTDOWN_FLUSH));
 }
 
-#[debug_handler]
-async fn stream_sql_response(
-    State(state): State<AppState>,
-    Query(params): Query<Vec<(String, String)>>,
-) -> impl IntoResponse {
-    let sources: Vec<String> = params
-        .iter()
-        .filter_map(|(key, value)| match key.as_str() {
-            "source" => Some(value),
-            _ => None,
-        })
-        .cloned()
-        .collect();
+fn bind(addr: SocketAddr) -> Result<std::net::TcpListener> {
+    std::net::TcpListener::bind(addr).with_context(|| format!("Failed to listen on {}", addr))
+}
 
-    let (tx, rx): (
-        UnboundedSender<Result<String, anyhow::Error>>,
-        UnboundedReceiver<Result<String, anyhow::Error>>,
-    ) = unbounded_channel();
-    {
-        if state.statements.read().await.check() {
-            let mut statements_w = state.statements.write().await;
-            statements_w
-                .recompile(state.client_pool.clone())
-                .await
-                .unwrap();
+async fn check(args: CheckArgs) -> Result<()> {
+    let client_pool = Arc::new(create_pool().await?);
+    let (templates, statements) = args.project.project().compile(client_pool.clone()).await?;
+    let client_pool = (!args.offline).then_some(client_pool);
+    match check::check(&templates, &statements, client_pool, args.sample).await? {
+        0 => {
+            println!("No problems found");
+            Ok(())
         }
+        problems => Err(anyhow::anyhow!("Problems found: {}", problems)).context(ProjectError),
     }
+}
 
-    tokio::spawn(async move {
-        let statements = state.statements.read().await;
-        match send_sql_results(state.client_pool, &statements, sources, tx.clone()).await {
-            Ok(_) => match tx.send(Ok("event: stream_stop\ndata: \n\n".to_string())) {
-                Ok(_) => tokio::time::sleep(tokio::time::Duration::from_millis(100)).await,
-                Err(e) => eprintln!("Final message send failed {}", e),
-            },
-            Err(e) => eprintln!("SQL result send failed: {e}"),
-        }
-    });
+// Compiles every template and SQL file into a bundle for production. Any file
+// that fails to compile fails the build.
+async fn build(args: BuildArgs) -> Result<()> {
+    let client_pool = Arc::new(create_pool().await?);
+    let (templates, statements) = args.project.project().compile(client_pool).await?;
+    Bundle::new(&templates, &statements).write(&args.out)?;
+    println!(
+        "Wrote {} templates and {} SQL files to {}",
+        templates.templates().len(),
+        statements.statements().len(),
+        args.out.display()
+    );
+    Ok(())
+}
 
-    let rx_stream = UnboundedReceiverStream::new(rx);
-    let body = Body::from_stream(rx_stream);
-    (
-        StatusCode::OK,
-        [
-            ("Content-Type", "text/event-stream"),
-            ("x-custom", "custom"),
-        ],
-        body,
+async fn export(args: ExportArgs) -> Result<()> {
+    let client_pool = Arc::new(create_pool().await?);
+    let (templates, statements) = args.project.project().compile(client_pool.clone()).await?;
+    export::export(
+        &templates,
+        &statements,
+        client_pool,
+        &args.project.project().www_dir(),
+        &args.out,
+        &normalize_base_path(&args.base_path),
+        args.snapshot,
     )
+    .await
+    .context(ProjectError)
 }
@@ -0,0 +1,60 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// The name of a template or SQL file: its path relative to the project
+// directory it's in, with `/` separators, as the collections key files.
+// Names arrive from query strings and template attributes, so they are
+// checked before they're used to find anything. A valid name has no empty,
+// `.` or `..` segments, doesn't start with `/`, and has no backslashes,
+// colons, percent signs or control characters, so it can only ever name a
+// file inside its directory, however it is later decoded or joined.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ResourceName(String);
+
+impl ResourceName {
+    pub fn parse(name: &str) -> Result<Self> {
+        let invalid = |reason: String| Err(anyhow!("Invalid file name {:?}: {}", name, reason));
+        if name.starts_with('/') {
+            return invalid("it is absolute".to_string());
+        }
+        if let Some(c) = name
+            .chars()
+            .find(|&c| matches!(c, '\\' | ':' | '%') || c.is_control())
+        {
+            return invalid(format!("it contains {:?}", c));
+        }
+        if name
+            .split('/')
+            .any(|segment| segment.is_empty() || segment == "." || segment == "..")
+        {
+            return invalid("it has an empty, . or .. segment".to_string());
+        }
+        Ok(ResourceName(name.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for ResourceName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl TryFrom<String> for ResourceName {
+    type Error = anyhow::Error;
+
+    fn try_from(name: String) -> Result<Self> {
+        ResourceName::parse(&name)
+    }
+}
+
+impl From<ResourceName> for String {
+    fn from(name: ResourceName) -> Self {
+        name.0
+    }
+}
@@ -0,0 +1,54 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::Path;
+
+const INDEX_HTML: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="UTF-8">
+  <meta name="viewport" content="width=device-width, initial-scale=1.0">
+  <title>Webware</title>
+</head>
+<body>
+  <x-route>
+    <x-path url="" file="home.html"/>
+  </x-route>
+</body>
+</html>
+"#;
+
+const HOME_HTML: &str = r#"<main>
+  <h1>Hello from webware</h1>
+  <p>The database says it is <span x-source="now.sql" x-text="data.now"></span>.</p>
+</main>
+"#;
+
+const NOW_SQL: &str = "SELECT json_build_object('now', now());\n";
+
+// Creates a project in `directory` with the layout the server expects, and a
+// page that reads from one source.
+pub fn new_project(directory: &Path) -> Result<()> {
+    let is_empty = match fs::read_dir(directory) {
+        Ok(mut entries) => entries.next().is_none(),
+        Err(_) => true,
+    };
+    if !is_empty {
+        return Err(anyhow!(
+            "{} already exists and isn't empty",
+            directory.display()
+        ));
+    }
+    let files = [
+        ("src/templates/index.html", INDEX_HTML),
+        ("src/templates/home.html", HOME_HTML),
+        ("src/sql/now.sql", NOW_SQL),
+    ];
+    for (path, contents) in files {
+        let path = directory.join(path);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, contents)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    fs::create_dir_all(directory.join("www/images"))?;
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+
+// Tells a router's event streams that the server is shutting down. Reload
+// streams end at once, since pages reconnect to them on their own. Source
+// streams have until the grace period ends to finish their queries; one still
+// running then is cut off, and its page told to reload once the server is
+// back.
+#[derive(Clone, Debug)]
+pub struct Shutdown {
+    begun: Arc<watch::Sender<bool>>,
+    grace: Duration,
+}
+
+impl Shutdown {
+    pub fn new(grace: Duration) -> Self {
+        Shutdown {
+            begun: Arc::new(watch::channel(false).0),
+            grace,
+        }
+    }
+
+    pub fn begin(&self) {
+        self.begun.send_replace(true);
+    }
+
+    pub fn grace(&self) -> Duration {
+        self.grace
+    }
+
+    // Resolves once shutdown begins, or never.
+    pub async fn begun(&self) {
+        let mut begun = self.begun.subscribe();
+        // The sender is never dropped while `self` holds it.
+        let _ = begun.wait_for(|begun| *begun).await;
+    }
+
+    // Resolves when the grace period after shutdown begins ends.
+    pub async fn deadline(&self) {
+        self.begun().await;
+        tokio::time::sleep(self.grace).await;
+    }
+}
+
+// A server that's never shut down, like one nested in another that doesn't
+// pass its shutdown on.
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown::new(Duration::ZERO)
+    }
+}
@@ -0,0 +1,128 @@
+use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use password_hash::rand_core::{OsRng, RngCore};
+use sha2::Sha256;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Signs payloads into tokens that the server can later trust without storing
+// them, like session cookies. A token is `payload.expires.signature`, with the
+// payload and signature base64url-encoded and `expires` in Unix seconds.
+#[derive(Clone)]
+pub struct Signer {
+    key: Vec<u8>,
+}
+
+impl Signer {
+    pub fn new(key: &[u8]) -> Result<Self> {
+        if key.len() < 32 {
+            return Err(anyhow!("Signing keys need at least 32 bytes"));
+        }
+        Ok(Signer { key: key.to_vec() })
+    }
+
+    // A key that only lasts as long as the process, so tokens signed with it
+    // stop working on restart.
+    pub fn random() -> Self {
+        let mut key = vec![0; 32];
+        OsRng.fill_bytes(&mut key);
+        Signer { key }
+    }
+
+    pub fn sign(&self, payload: &str, ttl: Duration) -> String {
+        let expires = (SystemTime::now() + ttl)
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let unsigned = format!("{}.{}", URL_SAFE_NO_PAD.encode(payload), expires);
+        let signature = URL_SAFE_NO_PAD.encode(self.mac(&unsigned).finalize().into_bytes());
+        format!("{}.{}", unsigned, signature)
+    }
+
+    // The payload of a token this signer signed, if it hasn't expired.
+    pub fn verify(&self, token: &str) -> Option<String> {
+        let (unsigned, signature) = token.rsplit_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        // Compared in constant time, so timing doesn't leak the signature.
+        self.mac(unsigned).verify_slice(&signature).ok()?;
+        let (payload, expires) = unsigned.split_once('.')?;
+        let expires = UNIX_EPOCH + Duration::from_secs(expires.parse().ok()?);
+        if expires < SystemTime::now() {
+            return None;
+        }
+        String::from_utf8(URL_SAFE_NO_PAD.decode(payload).ok()?).ok()
+    }
+
+    fn mac(&self, message: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC takes keys of any size");
+        mac.update(message.as_bytes());
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signer() -> Signer {
+        Signer::new(&[7; 32]).unwrap()
+    }
+
+    #[test]
+    fn verifies_its_own_tokens() {
+        let token = signer().sign("42", Duration::from_secs(60));
+        assert_eq!(signer().verify(&token).as_deref(), Some("42"));
+    }
+
+    #[test]
+    fn rejects_short_keys() {
+        assert!(Signer::new(&[7; 31]).is_err());
+    }
+
+    #[test]
+    fn rejects_tokens_from_another_key() {
+        let token = Signer::new(&[8; 32])
+            .unwrap()
+            .sign("42", Duration::from_secs(60));
+        assert_eq!(signer().verify(&token), None);
+    }
+
+    #[test]
+    fn rejects_tampered_tokens() {
+        let token = signer().sign("42", Duration::from_secs(60));
+        let (payload, rest) = token.split_once('.').unwrap();
+        let (expires, signature) = rest.split_once('.').unwrap();
+        let forged_payload = format!("{}.{}", URL_SAFE_NO_PAD.encode("1"), rest);
+        let later: u64 = expires.parse::<u64>().unwrap() + 3600;
+        let forged_expiry = format!("{}.{}.{}", payload, later, signature);
+        let mut forged_signature = token.clone();
+        let last = forged_signature.pop().unwrap();
+        forged_signature.push(if last == 'A' { 'B' } else { 'A' });
+        for token in [
+            forged_payload,
+            forged_expiry,
+            forged_signature,
+            format!("{}.{}", payload, expires),
+            String::new(),
+            "...".to_string(),
+        ] {
+            assert_eq!(signer().verify(&token), None, "{}", token);
+        }
+    }
+
+    #[test]
+    fn rejects_expired_tokens() {
+        // Signed as if it had expired a minute ago.
+        let expires = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            - 60;
+        let unsigned = format!("{}.{}", URL_SAFE_NO_PAD.encode("42"), expires);
+        let signature = URL_SAFE_NO_PAD.encode(signer().mac(&unsigned).finalize().into_bytes());
+        let token = format!("{}.{}", unsigned, signature);
+        assert_eq!(signer().verify(&token), None);
+    }
+}
@@ -1,8 +1,10 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
 #![allow(unused_imports)]
-use crate::{cache::compute_cache_key, AppState};
-use anyhow::Result;
+use crate::auth::{has_any_role, parse_roles, Forbidden, User};
+use crate::resource::ResourceName;
+use crate::{cache::FileSet, AppState};
+use anyhow::{anyhow, Context as _, Result};
 use axum::{
     body::Body,
     extract::{Query, State},
@@ -20,6 +22,7 @@ use futures::TryStreamExt;
 use futures::{pin_mut, stream::select};
 use futures::{stream, StreamExt};
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use serde_json::Value as Json;
 use std::convert::Infallible;
 use std::fs;
@@ -33,27 +36,63 @@ use std::task::{Context, Poll};
 use std::time::Instant;
 use std::{collections::HashMap, time::Duration};
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio_postgres::types::ToSql;
 use tokio_postgres::{NoTls, RowStream, Statement};
 
 pub async fn create_pool() -> Result<Pool> {
-    let mut cfg = Config::default();
-    cfg.dbname = Some("monitoring".to_string());
-    cfg.user = Some("adam".to_string());
-    cfg.password = Some("adam".to_string());
-    cfg.host = Some("haus".to_string());
+    let cfg = Config {
+        dbname: Some("monitoring".to_string()),
+        user: Some("adam".to_string()),
+        password: Some("adam".to_string()),
+        host: Some("haus".to_string()),
+        ..Default::default()
+    };
     let pool = cfg.create_pool(Some(Runtime::Tokio1), NoTls).unwrap();
     Ok(pool)
 }
 
+pub type Dataset = HashMap<String, Vec<Json>>;
+
+// Runs each source to completion and returns its rows, for rendering them
+// into the page rather than streaming them.
+pub async fn collect_sql_results(
+    client_pool: Arc<Pool>,
+    query_collection: &StatementCollection,
+    sources: Vec<ResourceName>,
+    user: Option<&User>,
+) -> Result<Dataset> {
+    let results = future::try_join_all(sources.into_iter().map(|source| {
+        let pool_clone = client_pool.clone();
+        async move {
+            let client = pool_clone.get().await?;
+            let source_file = query_collection.get(&source)?;
+            let params = source_file.bind_params(user)?;
+            let mut rows = Vec::new();
+            for query in &source_file.queries {
+                let statement = client.prepare_cached(query).await?;
+                let params = &params[..statement.params().len().min(params.len())];
+                for row in client.query(&statement, params).await? {
+                    let maybe_value: Option<Json> = row.get(0);
+                    rows.push(maybe_value.ok_or_else(|| anyhow::anyhow!("Missing value"))?);
+                }
+            }
+            Ok::<_, anyhow::Error>((String::from(source), rows))
+        }
+    }))
+    .await?;
+    Ok(results.into_iter().collect())
+}
+
 type ResultSender = UnboundedSender<Result<String, anyhow::Error>>;
 pub async fn send_sql_results(
     client_pool: Arc<Pool>,
     query_collection: &StatementCollection,
-    sources: Vec<String>,
+    sources: Vec<ResourceName>,
+    user: Option<&User>,
     tx: ResultSender,
 ) -> Result<()> {
     let error_signal = Arc::new(AtomicBool::new(false));
-    futures::stream::iter(sources.into_iter())
+    futures::stream::iter(sources)
         .for_each_concurrent(None, |source| {
             let tx_clone = tx.clone();
             let pool_clone = client_pool.clone();
@@ -63,9 +102,24 @@ pub async fn send_sql_results(
                     return;
                 }
                 let client = pool_clone.get().await.unwrap();
-                for query in query_collection.get(&source) {
-                    let sql_params: Vec<String> = vec![];
-                    let stream = client.query_raw(query, sql_params.iter()).await.unwrap();
+                let source_file = match query_collection.get(&source) {
+                    Ok(source_file) => source_file,
+                    Err(e) => {
+                        let _ = tx_clone.send(Err(e));
+                        err_clone.store(true, Ordering::Relaxed);
+                        return;
+                    }
+                };
+                // Sources are authorized before they're sent, which checks
+                // that their params can be bound.
+                let params = source_file.bind_params(user).unwrap();
+                for query in &source_file.queries {
+                    let statement = client.prepare_cached(query).await.unwrap();
+                    let params = &params[..statement.params().len().min(params.len())];
+                    let stream = client
+                        .query_raw(&statement, params.iter().map(|param| *param as &dyn ToSql))
+                        .await
+                        .unwrap();
                     pin_mut!(stream);
                     while let Some(Ok(row)) = stream.next().await {
                         if err_clone.load(Ordering::Relaxed) {
@@ -95,69 +149,195 @@ pub async fn send_sql_results(
     }
 }
 
+// Values from the request that a source's statements can use as `$1`, `$2`,
+// ..., in the order its `-- params:` header lists them. A statement is given
+// as many of them as it uses.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Param {
+    UserId,
+    Username,
+}
+
+impl TryFrom<&str> for Param {
+    type Error = anyhow::Error;
+
+    fn try_from(name: &str) -> Result<Self> {
+        match name {
+            "user_id" => Ok(Param::UserId),
+            "username" => Ok(Param::Username),
+            _ => Err(anyhow!("Unknown param {}", name)),
+        }
+    }
+}
+
+// A SQL file's statements, and the header comments at its top that control
+// who can read it and what it is given:
+//
+//     -- roles: finance, admin
+//     -- params: user_id
+//     SELECT json_build_object(...) FROM accounts WHERE owner = $1;
+//
+// Users need one of the roles to read the source; without the header, any
+// user can.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Source {
+    pub queries: Vec<String>,
+    pub roles: Vec<String>,
+    pub params: Vec<Param>,
+}
+
+impl Source {
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut roles = Vec::new();
+        let mut params = Vec::new();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some(comment) = line.strip_prefix("--") else {
+                break;
+            };
+            match comment.trim().split_once(':') {
+                Some(("roles", value)) => roles.extend(parse_roles(value)),
+                Some(("params", value)) => {
+                    for name in value.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                        params.push(Param::try_from(name)?);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let queries = content
+            .split(';')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+        Ok(Source {
+            queries,
+            roles,
+            params,
+        })
+    }
+
+    fn bind_params<'a>(&self, user: Option<&'a User>) -> Result<Vec<&'a (dyn ToSql + Sync)>> {
+        if self.params.is_empty() {
+            return Ok(Vec::new());
+        }
+        let user = user.ok_or_else(|| anyhow!("Source params need a signed-in user"))?;
+        Ok(self
+            .params
+            .iter()
+            .map(|param| match param {
+                Param::UserId => &user.id as &(dyn ToSql + Sync),
+                Param::Username => &user.username as &(dyn ToSql + Sync),
+            })
+            .collect())
+    }
+}
+
+#[derive(Clone)]
 pub struct StatementCollection {
-    directory: PathBuf,
-    cache_key: u64,
-    cache: HashMap<String, Vec<String>>,
+    // None when the statements were loaded from a bundle.
+    files: Option<FileSet>,
+    cache: HashMap<String, Source>,
 }
 
 impl StatementCollection {
-    pub fn new(directory: PathBuf) -> Self {
+    pub fn new(directory: PathBuf, hash_contents: bool) -> Self {
         StatementCollection {
-            directory,
-            cache_key: 0,
+            files: Some(FileSet::new(directory, hash_contents)),
             cache: HashMap::new(),
         }
     }
 
-    pub fn check(&self) -> bool {
-        let new_key = compute_cache_key(&self.directory).unwrap();
-        self.cache_key != new_key
+    pub fn from_bundle(statements: HashMap<String, Source>) -> Self {
+        StatementCollection {
+            files: None,
+            cache: statements,
+        }
+    }
+
+    // A copy of just the `sources` there are, for queries that outlive the
+    // lock on the collection, like an event stream.
+    pub fn snapshot(&self, sources: &[ResourceName]) -> StatementCollection {
+        let cache = sources
+            .iter()
+            .filter_map(|name| Some((name.to_string(), self.cache.get(name.as_str())?.clone())))
+            .collect();
+        StatementCollection::from_bundle(cache)
     }
 
-    pub async fn recompile(&mut self, client_pool: Arc<Pool>) -> Result<()> {
-        let new_key = compute_cache_key(&self.directory).unwrap();
-        if self.cache_key != new_key {
-            self.cache_key = new_key;
-            self.prepare_statements(client_pool).await?;
+    pub fn statements(&self) -> &HashMap<String, Source> {
+        &self.cache
+    }
+
+    pub fn directory(&self) -> Option<&Path> {
+        self.files.as_ref().map(FileSet::directory)
+    }
+
+    // Re-reads only the files that changed since the last recompile, and
+    // returns whether there were any.
+    pub async fn recompile(&mut self, client_pool: Arc<Pool>) -> Result<bool> {
+        let Some(files) = &mut self.files else {
+            return Ok(false);
+        };
+        let changes = files.changes()?;
+        if changes.is_empty() {
+            return Ok(false);
         }
-        Ok(())
+        let prepared = Self::prepare_statements(&changes.changed, client_pool).await?;
+        for name in &changes.removed {
+            self.cache.remove(name);
+        }
+        self.cache.extend(prepared);
+        files.commit(changes);
+        Ok(true)
     }
 
-    pub async fn prepare_statements(&mut self, client_pool: Arc<Pool>) -> Result<()> {
+    pub async fn prepare_statements(
+        entries: &[(String, PathBuf)],
+        client_pool: Arc<Pool>,
+    ) -> Result<HashMap<String, Source>> {
         let now = Instant::now(); // get current time
-        let entries: Vec<_> = fs::read_dir(self.directory.clone())?
-            .map(|res| res.map(|e| e.path()))
-            .collect::<Result<Vec<_>, std::io::Error>>()?;
-
-        self.cache = entries
-            .into_par_iter()
-            .map(|path_buf| {
+        entries
+            .par_iter()
+            .map(|(fname, path_buf)| {
                 let file = File::open(path_buf.clone())?;
                 let mut reader = BufReader::new(file);
                 let mut file_content = String::new();
                 reader.read_to_string(&mut file_content)?;
-                let queries: Vec<String> = file_content
-                    .split(';')
-                    .map(|s| s.trim().to_string())
-                    .filter(|s| !s.is_empty())
-                    .collect();
-
-                let fname = path_buf
-                    .strip_prefix(&self.directory)?
-                    .to_path_buf()
-                    .into_os_string()
-                    .to_string_lossy()
-                    .to_string();
-                Ok((fname, queries))
+                let source = Source::parse(&file_content)
+                    .with_context(|| format!("Invalid header in {}", path_buf.display()))?;
+                Ok((fname.clone(), source))
             })
-            .collect::<Result<HashMap<String, Vec<String>>>>()?;
+            .collect::<Result<HashMap<String, Source>>>()
+    }
+
+    // Checks that the user can read each of the sources, before any of them
+    // are queried.
+    pub fn authorize(&self, sources: &[ResourceName], user: Option<&User>) -> Result<()> {
+        for name in sources {
+            let source = self
+                .cache
+                .get(name.as_str())
+                .ok_or_else(|| anyhow!("Couldn't find source: {}", name))?;
+            if !has_any_role(&source.roles, user) {
+                return Err(anyhow!(
+                    "{} requires one of: {}",
+                    name,
+                    source.roles.join(", ")
+                ))
+                .context(Forbidden);
+            }
+            source.bind_params(user).context(Forbidden)?;
+        }
         Ok(())
     }
 
-    fn get(&self, file_name: &String) -> &Vec<String> {
+    fn get(&self, file_name: &ResourceName) -> Result<&Source> {
         self.cache
-            .get(file_name)
-            .expect(&format!("Couldn't find source: {}", file_name))
+            .get(file_name.as_str())
+            .ok_or_else(|| anyhow!("Couldn't find source: {}", file_name))
     }
 }
@@ -5,20 +5,30 @@ use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Result;
 use html5gum::Doctype;
-use html5gum::{HtmlString, IoReader, StartTag, Token, Tokenizer};
+use html5gum::{
+    naive_next_state, DefaultEmitter, HtmlString, IoReader, StartTag, State, Token, Tokenizer,
+};
 use rayon::prelude::*;
 use std::cell::RefCell;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::convert::TryFrom;
 use std::fmt::Write;
 use std::fs;
 use std::fs::File;
 use std::io::{self, BufReader};
 use std::iter::Flatten;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Instant;
 
-use crate::cache::compute_cache_key;
+use crate::auth::{has_any_role, parse_roles, User};
+use crate::cache::FileSet;
+use crate::expr::{evaluate, to_display};
+use crate::js::{expression_fields, expression_path, ExpressionError};
+use crate::resource::ResourceName;
+use crate::sql::Dataset;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as Json;
 
 fn to_utf8(html_string: HtmlString) -> Result<String> {
     Ok(String::from_utf8(html_string.0)?)
@@ -42,27 +52,97 @@ fn is_void_element(tag_name: &String) -> bool {
             | "wbr"
             | "x-path"
             | "x-embed"
+            | "x-props"
     )
 }
 
-#[derive(Debug, Clone)]
+// The elements whose text the tokenizer reads as-is, without decoding
+// entities, so it is written back as-is.
+fn is_raw_text_element(tag_name: &str) -> bool {
+    matches!(
+        naive_next_state(tag_name.as_bytes()),
+        Some(State::RawText | State::ScriptData | State::PlainText)
+    )
+}
+
+fn is_url_attribute(name: &str) -> bool {
+    matches!(name, "href" | "src" | "action" | "formaction" | "poster")
+}
+
+// URLs like `/www/app.css`, which a base path has to be put in front of.
+// Protocol-relative URLs (`//cdn.example.com`) name another host.
+fn is_root_relative(url: &str) -> bool {
+    url.starts_with('/') && !url.starts_with("//")
+}
+
+fn base_path_json(base_path: &str) -> String {
+    script_json(base_path, "\"\"")
+}
+
+// JSON to inline in a `<script>`. Data from sources can hold anything, so
+// every `<`, `>` and `&` is written as a `\u` escape, which JSON and
+// JavaScript read as the same string, and which can't end the script or
+// start a comment the HTML parser sees.
+fn script_json<T: Serialize + ?Sized>(value: &T, fallback: &str) -> String {
+    serde_json::to_string(value)
+        .unwrap_or(fallback.into())
+        .replace('<', "\\u003c")
+        .replace('>', "\\u003e")
+        .replace('&', "\\u0026")
+}
+
+// Finds the first `{{name}}` prop placeholder in `s`, returning its byte range
+// and the prop name.
+fn find_prop(s: &str) -> Option<(usize, usize, &str)> {
+    let mut offset = 0;
+    while let Some(start) = s[offset..].find("{{").map(|i| i + offset) {
+        let end = s[start..].find("}}").map(|i| i + start + 2)?;
+        let name = s[start + 2..end - 2].trim();
+        if !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+        {
+            return Some((start, end, name));
+        }
+        offset = start + 2;
+    }
+    None
+}
+
+fn substitute_props(s: &str, props: &BTreeMap<String, String>) -> String {
+    let mut result = String::new();
+    let mut rest = s;
+    while let Some((start, end, name)) = find_prop(rest) {
+        result.push_str(&rest[..start]);
+        result.push_str(props.get(name).map_or(&rest[start..end], |value| value));
+        rest = &rest[end..];
+    }
+    result.push_str(rest);
+    result
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Route {
     paths: Vec<BTreeMap<String, String>>,
 }
 
 impl Route {
-    fn match_path(&self, url_path: &mut String) -> Result<String> {
-        assert_eq!('/', url_path.remove(0));
+    fn match_path(&self, url_path: &mut String) -> Result<&BTreeMap<String, String>> {
+        // A route at the end of the URL matches its `url=""` path.
+        if url_path.starts_with('/') {
+            url_path.remove(0);
+        }
 
         // find the index of the next slash
         let index = url_path.find('/').unwrap_or(url_path.len());
         let path_part: String = url_path.drain(..index).collect();
         for path in &self.paths {
             if Some(&path_part) == path.get("url") {
-                return Ok(path.get("file").expect("No file for path.").to_owned());
+                return Ok(path);
             }
         }
-        Err(anyhow::anyhow!("No match for path {}", url_path))
+        Err(anyhow::anyhow!("No match for path {}", path_part))
     }
     fn get_files(&self) -> Vec<String> {
         self.paths
@@ -72,47 +152,335 @@ impl Route {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum BindingKind {
+    Text,
+    Html,
+    Value,
+    Attr(String),
+    Class(String),
+    Style(String),
+}
+
+impl TryFrom<&str> for BindingKind {
+    type Error = anyhow::Error;
+    fn try_from(name: &str) -> Result<Self> {
+        let kind = match name.split_once(':') {
+            None => match name {
+                "text" => Some(BindingKind::Text),
+                "html" => Some(BindingKind::Html),
+                "value" => Some(BindingKind::Value),
+                _ => None,
+            },
+            Some((_, "")) => None,
+            Some(("attr", attr)) => Some(BindingKind::Attr(attr.to_string())),
+            Some(("class", class)) => Some(BindingKind::Class(class.to_string())),
+            Some(("style", property)) => Some(BindingKind::Style(property.to_string())),
+            Some(_) => None,
+        };
+        kind.ok_or_else(|| anyhow!("Unrecognized binding x-{}", name))
+    }
+}
+
+impl BindingKind {
+    fn to_js(&self) -> String {
+        match self {
+            BindingKind::Text => r#"kind:"text""#.to_string(),
+            BindingKind::Html => r#"kind:"html""#.to_string(),
+            BindingKind::Value => r#"kind:"value""#.to_string(),
+            BindingKind::Attr(name) => format!(r#"kind:"attr",name:"{}""#, name),
+            BindingKind::Class(name) => format!(r#"kind:"class",name:"{}""#, name),
+            BindingKind::Style(name) => format!(r#"kind:"style",name:"{}""#, name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Dynamic {
+    kind: BindingKind,
+    expr: String,
+    // The fields of the bound value the expression reads.
+    fields: BTreeSet<String>,
+}
+
+impl Dynamic {
+    fn new(kind: BindingKind, expr: String, param: &str) -> Result<Self> {
+        let fields = expression_fields(&expr, param)?;
+        Ok(Dynamic { kind, expr, fields })
+    }
+}
+
+// The fields an expression reads, again once its props are substituted.
+fn substituted_fields(expr: &str, fields: &BTreeSet<String>, param: &str) -> BTreeSet<String> {
+    match find_prop(expr) {
+        Some(_) => expression_fields(expr, param).unwrap_or_else(|_| fields.clone()),
+        None => fields.clone(),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Binding {
     module: Option<String>,
     source: Option<String>,
-    dynamic: String,
+    // The name the expressions use for the bound value: `data` at the top
+    // level, or the row variable inside an `x-for`.
+    param: String,
+    dynamic: Vec<Dynamic>,
+    each: Option<Each>,
 }
 
-impl From<BTreeMap<String, String>> for Binding {
-    fn from(mut x_attrs: BTreeMap<String, String>) -> Self {
+impl Binding {
+    fn new(mut x_attrs: BTreeMap<String, String>, param: &str) -> Result<Self> {
         let module = x_attrs.remove("module");
         let source = x_attrs.remove("source");
-        let dynamic: String = x_attrs
-            .iter()
-            .map(|(name, expr)| {
-                let js_fn = format!(r#"(data) => {}"#, expr);
-                format!(r#"{}: {}"#, name, js_fn)
-            })
-            .collect::<Vec<_>>()
-            .join(",");
-        Binding {
+        let dynamic = x_attrs
+            .into_iter()
+            .map(|(name, expr)| Dynamic::new(name.as_str().try_into()?, expr, param))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Binding {
             module,
             source,
-            dynamic: format!("{{{}}}", dynamic),
+            param: param.to_string(),
+            dynamic,
+            each: None,
+        })
+    }
+
+    fn with_props(&self, props: &BTreeMap<String, String>) -> Binding {
+        let substitute = |s: &String| substitute_props(s, props);
+        Binding {
+            module: self.module.as_ref().map(substitute),
+            source: self.source.as_ref().map(substitute),
+            param: self.param.clone(),
+            dynamic: self
+                .dynamic
+                .iter()
+                .map(|dynamic| {
+                    let expr = substitute(&dynamic.expr);
+                    Dynamic {
+                        kind: dynamic.kind.clone(),
+                        fields: substituted_fields(&expr, &dynamic.fields, &self.param),
+                        expr,
+                    }
+                })
+                .collect(),
+            each: self.each.as_ref().map(|each| Each {
+                items: substitute(&each.items),
+                fields: substituted_fields(
+                    &substitute(&each.items_expr),
+                    &each.fields,
+                    &self.param,
+                ),
+                items_path: match find_prop(&each.items_expr) {
+                    Some(_) => expression_path(&substitute(&each.items_expr), &self.param),
+                    None => each.items_path.clone(),
+                },
+                items_expr: substitute(&each.items_expr),
+                key: each.key.as_ref().map(substitute),
+                bindings: each
+                    .bindings
+                    .iter()
+                    .map(|binding| binding.with_props(props))
+                    .collect(),
+            }),
         }
     }
+
+    fn dynamic_to_js(&self) -> String {
+        let dynamic: Vec<String> = self
+            .dynamic
+            .iter()
+            .map(|dynamic| {
+                let js_fn = format!(r#"({}) => {}"#, self.param, dynamic.expr);
+                format!(r#"{{{},fn: {}}}"#, dynamic.kind.to_js(), js_fn)
+            })
+            .collect();
+        format!("[{}]", dynamic.join(","))
+    }
+
+    fn to_js(&self, modules: &mut HashMap<String, String>) -> String {
+        let mut js_binding = Vec::new();
+        if let Some(source) = &self.source {
+            js_binding.push(format!(r#"source:"{}""#, source));
+        }
+        if let Some(module_path) = &self.module {
+            let module_name = format!("m{}", modules.len());
+            modules.insert(module_name.clone(), module_path.clone());
+            js_binding.push(format!(r#"module:{}"#, module_name));
+        }
+        js_binding.push(format!(r#"dynamic: {}"#, self.dynamic_to_js()));
+        if let Some(each) = &self.each {
+            js_binding.push(format!(r#"each: {}"#, each.to_js(modules)));
+        }
+
+        format!(r#"{{ {} }}"#, js_binding.join(","))
+    }
+}
+
+// Row template metadata for an `x-for` element. The element itself is
+// rendered inside a `<template>`, and `bindings` holds the bindings found in
+// that row, in document order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Each {
+    items: String,
+    // The expression `items` wraps, and the fields of the bound value it reads.
+    items_expr: String,
+    fields: BTreeSet<String>,
+    // The field the rows are in, when `items` is only a field like `data.rows`.
+    items_path: Option<String>,
+    key: Option<String>,
+    bindings: Vec<Binding>,
+}
+
+impl Each {
+    fn to_js(&self, modules: &mut HashMap<String, String>) -> String {
+        let bindings: Vec<String> = self
+            .bindings
+            .iter()
+            .map(|binding| binding.to_js(modules))
+            .collect();
+        let mut js_each = vec![format!(r#"items: {}"#, self.items)];
+        if let Some(key) = &self.key {
+            js_each.push(format!(r#"key: {}"#, key));
+        }
+        js_each.push(format!(r#"bindings: [{}]"#, bindings.join(",")));
+        format!(r#"{{ {} }}"#, js_each.join(","))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Repeat {
+    var: String,
+    binding: Binding,
+    parts: Vec<TemplatePart>,
+}
+
+impl Repeat {
+    // Parses `x-for="row in data"`. `param` is the name of the value in scope
+    // around the loop, which `data` in the items expression refers to.
+    fn new(x_for: &str, key: Option<String>, source: Option<String>, param: &str) -> Result<Self> {
+        let (var, items) = x_for
+            .split_once(" in ")
+            .map(|(var, items)| (var.trim(), items.trim()))
+            .filter(|(var, items)| {
+                !items.is_empty()
+                    && var
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+                    && var.starts_with(|c: char| !c.is_ascii_digit())
+            })
+            .ok_or_else(|| anyhow!("Invalid x-for expression: {}", x_for))?;
+        let fields = expression_fields(items, param)?;
+        if let Some(key) = &key {
+            expression_fields(key, var)?;
+        }
+        let each = Each {
+            items: format!(r#"({}) => {}"#, param, items),
+            items_expr: items.to_string(),
+            fields,
+            items_path: expression_path(items, param),
+            key: key.map(|key| format!(r#"({}) => {}"#, var, key)),
+            bindings: Vec::new(),
+        };
+        Ok(Repeat {
+            var: var.to_string(),
+            binding: Binding {
+                module: None,
+                source,
+                param: param.to_string(),
+                dynamic: Vec::new(),
+                each: Some(each),
+            },
+            parts: Vec::new(),
+        })
+    }
+}
+
+// An `x-embed` of the template `file`, with the props given as its other
+// attributes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Embed {
+    file: String,
+    props: BTreeMap<String, String>,
+}
+
+// A page that is rendered inside the layout `file`, with `slots` filling the
+// layout's `<x-slot>` placeholders.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Layout {
+    file: String,
+    slots: BTreeMap<String, Vec<TemplatePart>>,
 }
 
+// An `<x-slot>` placeholder in a layout, with its default content.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Slot {
+    name: String,
+    parts: Vec<TemplatePart>,
+}
+
+// Elements whose content is collected separately from the rest of the
+// template.
+enum Capture {
+    Repeat(Box<Repeat>),
+    Layout(Layout),
+    Slot(Slot),
+}
+
+impl Capture {
+    fn parts(&mut self) -> Option<&mut Vec<TemplatePart>> {
+        match self {
+            Capture::Repeat(repeat) => Some(&mut repeat.parts),
+            Capture::Slot(slot) => Some(&mut slot.parts),
+            // Only slots are kept from the body of an `x-layout`.
+            Capture::Layout(_) => None,
+        }
+    }
+}
+
+// Only the compiled output is serialized into a bundle; the rest is state
+// that is empty once compilation finishes.
+#[derive(Serialize, Deserialize)]
 pub struct Template {
+    #[serde(skip)]
     tag_stack: Vec<String>,
     parts: Vec<TemplatePart>,
     partial_route: Option<Route>,
+    // Open captures, with the tag stack depth they close at.
+    #[serde(skip)]
+    captures: Vec<(usize, Capture)>,
+    // Defaults declared by `<x-props>`, if this template is a component.
+    props: Option<BTreeMap<String, String>>,
+    #[serde(skip)]
+    used_props: BTreeSet<String>,
+    // Tag stack depths of the open elements that carry a binding.
+    #[serde(skip)]
+    bound_depths: Vec<usize>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 enum TemplatePart {
     Content(String),
     HeadInjection,
     Route(Route),
-    Embed(String),
+    Embed(Embed),
+    Prop(String),
+    // A prop inside a raw-text element like `script`, which isn't escaped.
+    RawProp(String),
     BodyInjection,
     Binding(Binding),
+    // Marks the content of a bound element, which server-side rendering
+    // replaces with the bound text.
+    BoundContent,
+    BoundEnd,
+    // Where the base path goes, in front of a root-relative URL.
+    BasePath,
+    // Where a `script` tag's nonce goes, so that the project's own scripts
+    // run under the page's Content Security Policy.
+    Nonce,
+    Repeat(Repeat),
+    Layout(Layout),
+    Slot(Slot),
 }
 
 impl From<&str> for TemplatePart {
@@ -127,36 +495,138 @@ impl From<String> for TemplatePart {
     }
 }
 
-impl TryFrom<HtmlString> for TemplatePart {
-    type Error = anyhow::Error;
-    fn try_from(html_s: HtmlString) -> Result<Self> {
-        Ok(TemplatePart::Content(to_utf8(html_s)?))
-    }
-}
-
-type TokenStream = Flatten<Tokenizer<IoReader<BufReader<File>>>>;
 impl Template {
-    pub fn compile(tokens: TokenStream) -> Result<Template> {
+    pub fn compile(tokens: impl Iterator<Item = Token>) -> Result<Template> {
         let mut template = Template {
             tag_stack: vec![],
             parts: vec![TemplatePart::Content(String::new())],
             partial_route: None,
+            captures: Vec::new(),
+            props: None,
+            used_props: BTreeSet::new(),
+            bound_depths: Vec::new(),
         };
         for token in tokens {
-            let new_parts = template.push_token(token)?;
-            template.parts.extend(new_parts);
+            template.push_token(token)?;
+        }
+        if let Some((_, capture)) = template.captures.last() {
+            return Err(match capture {
+                Capture::Repeat(repeat) => anyhow!("Unclosed x-for for {}", repeat.var),
+                Capture::Layout(layout) => anyhow!("Unclosed x-layout for {}", layout.file),
+                Capture::Slot(slot) => anyhow!("Unclosed x-slot {}", slot.name),
+            });
+        }
+        if let Some(declared) = &template.props {
+            if let Some(name) = template
+                .used_props
+                .difference(&declared.keys().cloned().collect())
+                .next()
+            {
+                return Err(anyhow!("Prop {} is not declared in x-props", name));
+            }
         }
         Ok(template)
     }
-    fn push_token(&mut self, token: Token) -> Result<Vec<TemplatePart>> {
+    fn push_token(&mut self, token: Token) -> Result<()> {
         match token {
-            Token::Doctype(doc_type) => self.handle_doctype(doc_type),
-            Token::StartTag(tag) => self.handle_start_tag(tag),
-            Token::EndTag(tag) => self.handle_end_tag(tag.name),
-            Token::String(html_string) => Ok(vec![html_string.try_into()?]),
-            Token::Comment(_) => Ok(Vec::new()),
-            Token::Error(err) => Err(anyhow::anyhow!("Error {:?}", err)),
+            Token::Doctype(doc_type) => {
+                let parts = self.handle_doctype(doc_type)?;
+                self.emit(parts);
+            }
+            Token::StartTag(tag) => self.handle_start_tag(tag)?,
+            Token::EndTag(tag) => self.handle_end_tag(tag.name)?,
+            Token::String(html_string) => self.handle_text(to_utf8(html_string)?),
+            Token::Comment(_) => {}
+            Token::Error(err) => return Err(anyhow::anyhow!("Error {:?}", err)),
+        }
+        Ok(())
+    }
+
+    // The tokenizer decodes entities in text, so text is escaped again to
+    // come out as the same text, except in the elements it reads as-is.
+    fn handle_text(&mut self, text: String) {
+        match self.tag_stack.last() {
+            Some(tag_name) if is_raw_text_element(tag_name) => {
+                let parts = self.split_props(vec![text.into()], true);
+                self.emit_split(parts);
+            }
+            _ => self.emit(vec![escape_text(&text).into()]),
+        }
+    }
+
+    // Parts inside an `x-for` element belong to its row template, and parts
+    // inside an `x-slot` to that slot.
+    fn emit(&mut self, parts: Vec<TemplatePart>) {
+        let parts = self.split_props(parts, false);
+        self.emit_split(parts);
+    }
+
+    fn emit_split(&mut self, parts: Vec<TemplatePart>) {
+        match self.captures.last_mut() {
+            Some((_, capture)) => {
+                if let Some(capture_parts) = capture.parts() {
+                    capture_parts.extend(parts);
+                }
+            }
+            None => self.parts.extend(parts),
+        }
+    }
+
+    // Splits `{{name}}` placeholders out of content, so that embedding the
+    // template with props doesn't need to scan it again.
+    fn split_props(&mut self, parts: Vec<TemplatePart>, raw: bool) -> Vec<TemplatePart> {
+        let mut split = Vec::with_capacity(parts.len());
+        for part in parts {
+            let TemplatePart::Content(content) = part else {
+                split.push(part);
+                continue;
+            };
+            let mut rest = content.as_str();
+            while let Some((start, end, name)) = find_prop(rest) {
+                split.push(rest[..start].into());
+                split.push(match raw {
+                    true => TemplatePart::RawProp(name.to_string()),
+                    false => TemplatePart::Prop(name.to_string()),
+                });
+                self.used_props.insert(name.to_string());
+                rest = &rest[end..];
+            }
+            split.push(rest.into());
+        }
+        split
+    }
+
+    fn scope(&self) -> &str {
+        self.captures
+            .iter()
+            .rev()
+            .find_map(|(_, capture)| match capture {
+                Capture::Repeat(repeat) => Some(repeat.var.as_str()),
+                _ => None,
+            })
+            .unwrap_or("data")
+    }
+
+    fn open_capture(&mut self, capture: Capture) {
+        self.captures.push((self.tag_stack.len() - 1, capture));
+    }
+
+    fn close_capture(&mut self) -> Result<()> {
+        let (_, capture) = self.captures.pop().expect("No open capture");
+        match capture {
+            Capture::Repeat(repeat) => self.emit(vec![TemplatePart::Repeat(*repeat)]),
+            Capture::Layout(layout) => self.emit(vec![TemplatePart::Layout(layout)]),
+            // Inside an `x-layout`, a slot fills the layout's placeholder.
+            Capture::Slot(slot) => match self.captures.last_mut() {
+                Some((_, Capture::Layout(layout))) => {
+                    if layout.slots.insert(slot.name.clone(), slot.parts).is_some() {
+                        return Err(anyhow!("Duplicate x-slot {}", slot.name));
+                    }
+                }
+                _ => self.emit(vec![TemplatePart::Slot(slot)]),
+            },
         }
+        Ok(())
     }
 
     fn push_tag(&mut self, tag_name: &String) {
@@ -164,7 +634,7 @@ impl Template {
         // println!("{:?}", self.tag_stack);
     }
 
-    fn pop_tag(&mut self, tag_name: &String) {
+    fn pop_tag(&mut self, tag_name: &String) -> Result<()> {
         let expected_tag = self.tag_stack.pop().expect("Tag stack is empty");
         // if &expected_tag != tag_name{
         //     for s in self.parts.iter().rev().take(8) {
@@ -174,12 +644,18 @@ impl Template {
         assert_eq!(&expected_tag, tag_name);
         // println!("{:?}", self.tag_stack);
         if expected_tag.as_str() == "x-route" {
-            self.parts
-                .push(TemplatePart::Route(self.partial_route.take().unwrap()));
+            let route = self.partial_route.take().unwrap();
+            self.emit(vec![TemplatePart::Route(route)]);
+        }
+        if let Some((depth, _)) = self.captures.last() {
+            if *depth == self.tag_stack.len() {
+                self.close_capture()?;
+            }
         }
+        Ok(())
     }
 
-    fn handle_start_tag(&mut self, tag: StartTag) -> Result<Vec<TemplatePart>> {
+    fn handle_start_tag(&mut self, tag: StartTag) -> Result<()> {
         let tag_name = to_utf8(tag.name)?;
         self.push_tag(&tag_name);
         let attrs: BTreeMap<_, _> = tag
@@ -187,6 +663,13 @@ impl Template {
             .into_iter()
             .map(|(key, value)| Ok((to_utf8(key)?, to_utf8(value)?)))
             .collect::<Result<_>>()?;
+        for value in attrs.values() {
+            let mut rest = value.as_str();
+            while let Some((_, end, name)) = find_prop(rest) {
+                self.used_props.insert(name.to_string());
+                rest = &rest[end..];
+            }
+        }
         let result: Result<Vec<TemplatePart>> = match tag_name.as_str() {
             "x-route" => {
                 assert!(self.partial_route.is_none());
@@ -201,19 +684,66 @@ impl Template {
                     .ok_or(anyhow::anyhow!("Found path outside of route"))?;
                 Ok(Vec::new())
             }
+            "x-layout" => {
+                let file = attrs
+                    .get("file")
+                    .ok_or_else(|| anyhow!("x-layout missing \"file\""))?;
+                self.open_capture(Capture::Layout(Layout {
+                    file: file.to_string(),
+                    slots: BTreeMap::new(),
+                }));
+                Ok(Vec::new())
+            }
+            "x-slot" => {
+                let name = attrs
+                    .get("name")
+                    .ok_or_else(|| anyhow!("x-slot missing \"name\""))?;
+                self.open_capture(Capture::Slot(Slot {
+                    name: name.to_string(),
+                    parts: Vec::new(),
+                }));
+                Ok(Vec::new())
+            }
             "x-embed" => {
                 println!("EMERDB {:?}", &attrs);
-                attrs
-                    .get("file")
-                    .map(|template| Ok(vec![TemplatePart::Embed(template.to_string())]))
+                let mut props = attrs;
+                props
+                    .remove("file")
+                    .map(|file| Ok(vec![TemplatePart::Embed(Embed { file, props })]))
                     .unwrap_or_else(|| Err(anyhow::anyhow!("x-embed missing \"file\"")))
             }
+            "x-props" => {
+                if self.props.replace(attrs).is_some() {
+                    return Err(anyhow!("Duplicate x-props"));
+                }
+                Ok(Vec::new())
+            }
+            _ if attrs.contains_key("x-for") => {
+                self.open_repeat(&tag_name, attrs, tag.self_closing)
+            }
             _ => Ok(self.convert_tag(&tag_name, attrs, tag.self_closing)?),
         };
+        // Emit before popping, so that a void `x-for` element ends up inside
+        // its own row template.
+        self.emit(result?);
         if tag.self_closing || is_void_element(&tag_name) {
-            self.pop_tag(&tag_name);
+            self.pop_tag(&tag_name)?;
         }
-        result
+        Ok(())
+    }
+
+    fn open_repeat(
+        &mut self,
+        tag_name: &String,
+        mut attrs: BTreeMap<String, String>,
+        self_closing: bool,
+    ) -> Result<Vec<TemplatePart>> {
+        let x_for = attrs.remove("x-for").unwrap_or_default();
+        let key = attrs.remove("x-key");
+        let source = attrs.remove("x-source");
+        let repeat = Repeat::new(&x_for, key, source, self.scope())?;
+        self.open_capture(Capture::Repeat(Box::new(repeat)));
+        self.convert_tag(tag_name, attrs, self_closing)
     }
 
     fn convert_tag(
@@ -224,34 +754,56 @@ impl Template {
     ) -> Result<Vec<TemplatePart>> {
         let mut parts: Vec<TemplatePart> = Vec::new();
         parts.push(format!("<{}", tag_name).into());
+        let is_script = tag_name == "script";
+        if is_script {
+            parts.push(TemplatePart::Nonce);
+        }
         let mut x_attrs = BTreeMap::new();
         for (attr_name, attr_value) in attributes {
             match attr_name.as_str() {
+                // Only the response's own nonce is any use.
+                "nonce" if is_script => {}
                 name if name.starts_with("x-") => {
                     let strip_name: String = name.chars().skip(2).collect();
                     x_attrs.insert(strip_name, attr_value);
                 }
+                _ if is_url_attribute(&attr_name) && is_root_relative(&attr_value) => {
+                    parts.push(format!(" {}=\"", attr_name).into());
+                    parts.push(TemplatePart::BasePath);
+                    parts.push(format!("{}\"", escape_attribute(&attr_value)).into());
+                }
                 _ => {
                     parts.push(" ".into());
                     parts.push(attr_name.into());
                     if !attr_value.is_empty() {
-                        parts.push(format!("=\"{}\"", attr_value).into());
+                        parts.push(format!("=\"{}\"", escape_attribute(&attr_value)).into());
                     }
                 }
             }
         }
-        if !x_attrs.is_empty() {
-            parts.push(TemplatePart::Binding(x_attrs.into()));
+        let bound = !x_attrs.is_empty();
+        if bound {
+            let binding = Binding::new(x_attrs, self.scope())
+                .with_context(|| format!("Invalid bindings on <{}>", tag_name))?;
+            parts.push(TemplatePart::Binding(binding));
             parts.push(" data-bound".into());
         }
         if self_closing {
             parts.push("/".into());
         }
         parts.push(">".into());
+        if bound {
+            parts.push(TemplatePart::BoundContent);
+            if self_closing || is_void_element(tag_name) {
+                parts.push(TemplatePart::BoundEnd);
+            } else {
+                self.bound_depths.push(self.tag_stack.len());
+            }
+        }
         Ok(parts)
     }
 
-    fn handle_end_tag(&mut self, name: HtmlString) -> Result<Vec<TemplatePart>> {
+    fn handle_end_tag(&mut self, name: HtmlString) -> Result<()> {
         let mut parts: Vec<TemplatePart> = Vec::new();
         let tag_name = to_utf8(name)?;
         if tag_name == "head" {
@@ -261,11 +813,15 @@ impl Template {
             parts.push(TemplatePart::BodyInjection);
         }
 
+        if self.bound_depths.last() == Some(&self.tag_stack.len()) {
+            self.bound_depths.pop();
+            parts.push(TemplatePart::BoundEnd);
+        }
         if !tag_name.starts_with("x-") {
             parts.push(format!("</{}>", tag_name).into());
         }
-        self.pop_tag(&tag_name);
-        Ok(parts)
+        self.emit(parts);
+        self.pop_tag(&tag_name)
     }
 
     fn handle_doctype(&self, doc_type: Doctype) -> Result<Vec<TemplatePart>> {
@@ -274,193 +830,797 @@ impl Template {
     }
 }
 
+const LIVE_RELOAD: &str = r#"
+              const reloadSource = new EventSource(basePath + "/__webware/reload");
+              reloadSource.addEventListener("templates", () => location.reload());
+              reloadSource.addEventListener("sql", () =>
+                window.refreshSources ? window.refreshSources() : location.reload(),
+              );
+"#;
+
+// The tokenizer doesn't track positions, so the line of an invalid expression
+// is found by looking for it in the template's source.
+fn error_line(error: &anyhow::Error, source: &str) -> Option<usize> {
+    let expr = &error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<ExpressionError>())?
+        .expr;
+    let offset = source
+        .find(expr.as_str())
+        .or_else(|| source.find(&escape_attribute(expr)))?;
+    Some(source[..offset].matches('\n').count() + 1)
+}
+
+// Shown in development in place of every page while a template or SQL file
+// fails to build. It reloads once the file is fixed.
+pub fn error_page(error: &str, base_path: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="UTF-8">
+  <title>Build error</title>
+  <script>
+    const basePath = {}
+    {}
+  </script>
+</head>
+<body style="font-family: monospace; margin: 2rem">
+  <h1 style="color: #b91c1c">Build error</h1>
+  <pre style="white-space: pre-wrap">{}</pre>
+</body>
+</html>
+"#,
+        base_path_json(base_path),
+        LIVE_RELOAD,
+        escape_html(error)
+    )
+}
+
+// The framework's client code is part of the binary, so that a server
+// doesn't depend on the directory it was started from.
+const PREAMBLE: &str = include_str!("../www/preamble.js");
+
+// Templates are shared between the collection being served and the copy a
+// rebuild works on, so only changed files have to be compiled again.
+#[derive(Clone)]
 pub struct TemplateCollection {
-    preamble: String,
-    directory: PathBuf,
-    cache_key: u64,
-    cache: HashMap<String, Template>,
+    // None when the templates were loaded from a bundle.
+    files: Option<FileSet>,
+    cache: HashMap<String, Arc<Template>>,
 }
 
 impl TemplateCollection {
-    pub fn new(directory: PathBuf) -> Self {
-        let preamble = fs::read_to_string("www/preamble.js").unwrap();
+    pub fn new(directory: PathBuf, hash_contents: bool) -> Self {
         TemplateCollection {
-            preamble,
-            directory,
-            cache_key: 0,
+            files: Some(FileSet::new(directory, hash_contents)),
             cache: HashMap::new(),
         }
     }
 
-    pub fn check(&self) -> bool {
-        let new_key = compute_cache_key(&self.directory).unwrap();
-        self.cache_key != new_key
+    pub fn from_bundle(templates: HashMap<String, Arc<Template>>) -> Self {
+        TemplateCollection {
+            files: None,
+            cache: templates,
+        }
+    }
+
+    pub fn templates(&self) -> &HashMap<String, Arc<Template>> {
+        &self.cache
     }
 
-    pub fn recompile(&mut self) -> Result<()> {
-        let new_key = compute_cache_key(&self.directory).unwrap();
-        if self.cache_key != new_key {
-            self.cache_key = new_key;
-            self.compile_templates()?;
+    pub fn directory(&self) -> Option<&Path> {
+        self.files.as_ref().map(FileSet::directory)
+    }
+
+    // Compiles only the files that changed since the last recompile, and
+    // returns whether there were any.
+    pub fn recompile(&mut self) -> Result<bool> {
+        let Some(files) = &mut self.files else {
+            return Ok(false);
+        };
+        let changes = files.changes()?;
+        if changes.is_empty() {
+            return Ok(false);
         }
-        Ok(())
+        let compiled = Self::compile_templates(&changes.changed)?;
+        for name in &changes.removed {
+            self.cache.remove(name);
+        }
+        self.cache.extend(compiled);
+        files.commit(changes);
+        Ok(true)
     }
 
-    fn compile_templates(&mut self) -> Result<()> {
+    fn compile_templates(entries: &[(String, PathBuf)]) -> Result<Vec<(String, Arc<Template>)>> {
         let now = Instant::now(); // get current time
-        let entries: Vec<_> = fs::read_dir(self.directory.clone())?
-            .map(|res| res.map(|e| e.path()))
-            .collect::<Result<Vec<_>, std::io::Error>>()?;
-
-        self.cache = entries
-            .into_par_iter()
-            .map(|path_buf| {
-                let file = File::open(path_buf.clone())
+
+        let compiled = entries
+            .par_iter()
+            .map(|(fname, path_buf)| {
+                let source = fs::read_to_string(path_buf)
                     .with_context(|| format!("Failed to open file {}", path_buf.display()))?;
-                let reader = BufReader::new(file);
-                let tokenizer = Tokenizer::new(IoReader::new(reader)).flatten();
-                let template = Template::compile(tokenizer)?;
-                let fname = path_buf
-                    .strip_prefix(&self.directory)?
-                    .to_path_buf()
-                    .into_os_string()
-                    .to_string_lossy()
-                    .to_string();
-                Ok((fname, template))
+                // Switching states reads the text of `script`, `style` and
+                // the like as text rather than as markup.
+                let mut emitter = DefaultEmitter::default();
+                emitter.switch_states(true);
+                let tokenizer = Tokenizer::new_with_emitter(source.as_str(), emitter).flatten();
+                let template = Template::compile(tokenizer).map_err(|e| {
+                    let location = match error_line(&e, &source) {
+                        Some(line) => format!("{}:{}", path_buf.display(), line),
+                        None => path_buf.display().to_string(),
+                    };
+                    e.context(format!("Failed to compile {}", location))
+                })?;
+                Ok((fname.clone(), Arc::new(template)))
             })
-            .collect::<Result<HashMap<String, Template>>>()?;
+            .collect::<Result<Vec<_>>>()?;
 
         let elapsed = now.elapsed(); // get elapsed time
-        println!("Template compilation took {:?}", elapsed);
-        Ok(())
+        println!(
+            "Template compilation of {} files took {:?}",
+            entries.len(),
+            elapsed
+        );
+        Ok(compiled)
     }
 
-    pub fn get_page(&self, mut url_path: String) -> Result<String> {
-        let mut page = Page {
-            preamble: self.preamble.clone(),
-            parts: Vec::new(),
-            sources: HashSet::new(),
-            bindings: Vec::new(),
+    pub fn get_page(&self, url_path: String) -> Result<String> {
+        Ok(self.build_page(url_path)?.render(None))
+    }
+
+    pub fn build_page(&self, mut url_path: String) -> Result<Page> {
+        let mut page = Page::default();
+        let index = ResourceName::parse("index.html")?;
+        self.collect_parts(
+            &mut url_path,
+            &index,
+            &mut page,
+            Scope::default(),
+            &mut Vec::new(),
+        )?;
+        Ok(page)
+    }
+
+    pub fn contains(&self, file_name: &str) -> bool {
+        ResourceName::parse(file_name).is_ok_and(|name| self.cache.contains_key(name.as_str()))
+    }
+
+    // Builds a page from one template instead of from a URL, with `props` in
+    // scope, for pages the server renders itself.
+    pub fn build_template_page(
+        &self,
+        file_name: &str,
+        props: &BTreeMap<String, String>,
+    ) -> Result<Page> {
+        let mut page = Page::default();
+        let scope = Scope {
+            props: Some(props),
+            slots: None,
         };
-        self.collect_parts(&mut url_path, "index.html".to_string(), &mut page)?;
-        Ok(page.render())
+        let file_name = ResourceName::parse(file_name)?;
+        self.collect_parts(
+            &mut String::new(),
+            &file_name,
+            &mut page,
+            scope,
+            &mut Vec::new(),
+        )?;
+        Ok(page)
+    }
+
+    // The URL of every page reachable through the `x-route`s of index.html
+    // and the templates it embeds.
+    pub fn routes(&self) -> Result<Vec<String>> {
+        let mut urls = self.file_routes("index.html", "", &mut Vec::new())?;
+        if urls.is_empty() {
+            urls.push("/".to_string());
+        }
+        Ok(urls)
+    }
+
+    fn file_routes(
+        &self,
+        file_name: &str,
+        prefix: &str,
+        stack: &mut Vec<String>,
+    ) -> Result<Vec<String>> {
+        // A template that embeds itself adds no routes the first time didn't.
+        if stack.iter().any(|file| file == file_name) {
+            return Ok(Vec::new());
+        }
+        let template = self
+            .cache
+            .get(file_name)
+            .ok_or_else(|| anyhow!("Unable to find: {}", file_name))?;
+        stack.push(file_name.to_string());
+        let urls = self.parts_routes(&template.parts, prefix, stack);
+        stack.pop();
+        urls
+    }
+
+    fn parts_routes(
+        &self,
+        parts: &[TemplatePart],
+        prefix: &str,
+        stack: &mut Vec<String>,
+    ) -> Result<Vec<String>> {
+        let mut urls = Vec::new();
+        for part in parts {
+            match part {
+                TemplatePart::Repeat(repeat) => {
+                    urls.extend(self.parts_routes(&repeat.parts, prefix, stack)?)
+                }
+                TemplatePart::Slot(slot) => {
+                    urls.extend(self.parts_routes(&slot.parts, prefix, stack)?)
+                }
+                TemplatePart::Layout(layout) => {
+                    for parts in layout.slots.values() {
+                        urls.extend(self.parts_routes(parts, prefix, stack)?);
+                    }
+                    urls.extend(self.file_routes(&layout.file, prefix, stack)?);
+                }
+                TemplatePart::Embed(embed) => {
+                    urls.extend(self.file_routes(&embed.file, prefix, stack)?)
+                }
+                TemplatePart::Route(route) => {
+                    for path in &route.paths {
+                        let (Some(url), Some(file)) = (path.get("url"), path.get("file")) else {
+                            continue;
+                        };
+                        let page_url = format!("{}/{}", prefix, url);
+                        // A missing file is still a route; rendering it
+                        // reports the error.
+                        let nested = match self.cache.contains_key(file) {
+                            true => self.file_routes(file, &page_url, stack)?,
+                            false => Vec::new(),
+                        };
+                        match nested.is_empty() {
+                            true => urls.push(page_url),
+                            false => urls.extend(nested),
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(urls)
     }
 
     fn collect_parts(
         &self,
         url_path: &mut String,
-        file_name: String,
+        file_name: &ResourceName,
         page: &mut Page,
+        scope: Scope,
+        stack: &mut Vec<String>,
     ) -> Result<()> {
         let template = self
             .cache
-            .get(&file_name)
-            .ok_or_else(|| anyhow!("Unable to find: {}", &file_name))?;
-        for part in template.parts.clone() {
-            if let Some(file_name) = self.resolve_reference(url_path, &part)? {
-                self.collect_parts(url_path, file_name, page)?
-            } else {
-                page.push_part(part);
-            }
-        }
-        Ok(())
+            .get(file_name.as_str())
+            .ok_or_else(|| anyhow!("Unable to find: {}", file_name))?;
+        stack.push(file_name.to_string());
+        let collected = template
+            .parts
+            .iter()
+            .try_for_each(|part| self.collect_part(url_path, part, page, scope, stack));
+        stack.pop();
+        collected
     }
 
-    fn resolve_reference(
+    // Layouts and embeds are inlined, so a template that includes itself
+    // would never finish.
+    fn check_cycle(kind: &str, file_name: &ResourceName, stack: &[String]) -> Result<()> {
+        let Some(start) = stack.iter().position(|file| file == file_name.as_str()) else {
+            return Ok(());
+        };
+        let mut cycle = stack[start..].to_vec();
+        cycle.push(file_name.to_string());
+        Err(anyhow!("{} cycle: {}", kind, cycle.join(" → ")))
+    }
+
+    fn collect_part(
         &self,
         url_path: &mut String,
         part: &TemplatePart,
-    ) -> Result<Option<String>> {
+        page: &mut Page,
+        scope: Scope,
+        stack: &mut Vec<String>,
+    ) -> Result<()> {
         match part {
-            TemplatePart::Embed(file_name) => Ok(Some(file_name.to_string())),
-            TemplatePart::Route(route) => route.match_path(url_path).map(|s| Some(s.to_owned())),
-            _ => Ok(None),
+            TemplatePart::Repeat(repeat) => {
+                page.open_repeat(scope.bind(&repeat.binding))?;
+                for part in &repeat.parts {
+                    self.collect_part(url_path, part, page, scope, stack)?;
+                }
+                page.close_repeat();
+            }
+            TemplatePart::Layout(layout) => {
+                let layout_slots = SlotScope {
+                    slots: &layout.slots,
+                    parent: scope,
+                };
+                let layout_scope = Scope {
+                    props: None,
+                    slots: Some(&layout_slots),
+                };
+                let file_name = ResourceName::parse(&layout.file)?;
+                Self::check_cycle("x-embed/x-layout", &file_name, stack)?;
+                self.collect_parts(url_path, &file_name, page, layout_scope, stack)?;
+            }
+            TemplatePart::Slot(slot) => {
+                // A filled slot is collected in the scope of the template
+                // that filled it; the default content in the layout's own.
+                let (parts, slot_scope) = match scope
+                    .slots
+                    .and_then(|slots| Some((slots.slots.get(&slot.name)?, slots)))
+                {
+                    Some((parts, slots)) => (parts, slots.parent),
+                    None => (&slot.parts, scope),
+                };
+                for part in parts {
+                    self.collect_part(url_path, part, page, slot_scope, stack)?;
+                }
+            }
+            TemplatePart::Embed(embed) => {
+                let props = self.embed_props(embed, scope)?;
+                let embed_scope = Scope {
+                    props: Some(&props),
+                    slots: scope.slots,
+                };
+                let file_name = ResourceName::parse(&embed.file)?;
+                Self::check_cycle("x-embed/x-layout", &file_name, stack)?;
+                self.collect_parts(url_path, &file_name, page, embed_scope, stack)?;
+            }
+            TemplatePart::Prop(name) | TemplatePart::RawProp(name) => {
+                // Prop values are attribute values the tokenizer decoded, so
+                // they are escaped like any text, except in raw-text elements.
+                let content = match scope.props.and_then(|props| props.get(name)) {
+                    Some(value) if matches!(part, TemplatePart::RawProp(_)) => value.clone(),
+                    Some(value) => escape_html(value),
+                    None => format!("{{{{{}}}}}", name),
+                };
+                page.push_part(content.into())?;
+            }
+            TemplatePart::Binding(binding) => {
+                page.push_part(TemplatePart::Binding(scope.bind(binding)))?;
+            }
+            TemplatePart::Route(route) => {
+                let remaining = url_path.len();
+                let path = route.match_path(url_path)?;
+                let file_name = ResourceName::parse(path.get("file").expect("No file for path."))?;
+                if let Some(roles) = path.get("roles") {
+                    page.access.push(parse_roles(roles));
+                }
+                // A route that matched a URL segment can't loop, since the
+                // URL runs out; one that matched the end of it can.
+                match url_path.len() < remaining {
+                    true => {
+                        self.collect_parts(url_path, &file_name, page, scope, &mut Vec::new())?
+                    }
+                    false => {
+                        Self::check_cycle("x-route", &file_name, stack)?;
+                        self.collect_parts(url_path, &file_name, page, scope, stack)?
+                    }
+                }
+            }
+            part => page.push_part(part.clone())?,
+        }
+        Ok(())
+    }
+
+    // The component's declared defaults, overridden by the embed's attributes.
+    // Attribute values can forward the embedding template's own props.
+    fn embed_props(&self, embed: &Embed, scope: Scope) -> Result<BTreeMap<String, String>> {
+        let file_name = ResourceName::parse(&embed.file)?;
+        let template = self
+            .cache
+            .get(file_name.as_str())
+            .ok_or_else(|| anyhow!("Unable to find: {}", &embed.file))?;
+        let mut props = template.props.clone().unwrap_or_default();
+        for (name, value) in &embed.props {
+            if template.props.is_some() && !props.contains_key(name) {
+                return Err(anyhow!("Unknown prop {} for {}", name, embed.file));
+            }
+            let value = match scope.props {
+                Some(outer) => substitute_props(value, outer),
+                None => value.clone(),
+            };
+            props.insert(name.clone(), value);
+        }
+        Ok(props)
+    }
+}
+
+// What a template is being collected with: the props it was embedded with,
+// and the slot fills of the layouts it is inside.
+#[derive(Clone, Copy, Default)]
+struct Scope<'a> {
+    props: Option<&'a BTreeMap<String, String>>,
+    slots: Option<&'a SlotScope<'a>>,
+}
+
+impl Scope<'_> {
+    fn bind(&self, binding: &Binding) -> Binding {
+        match self.props {
+            Some(props) => binding.with_props(props),
+            None => binding.clone(),
         }
     }
 }
 
-struct Page {
-    preamble: String,
+// The slot fills for the layout being collected, and for the layouts that
+// contain it.
+struct SlotScope<'a> {
+    slots: &'a BTreeMap<String, Vec<TemplatePart>>,
+    parent: Scope<'a>,
+}
+
+#[derive(Default)]
+pub struct Page {
     parts: Vec<TemplatePart>,
-    sources: HashSet<String>,
+    sources: HashSet<ResourceName>,
     bindings: Vec<Binding>,
+    // Row bindings of the `x-for` templates currently being collected.
+    repeats: Vec<Vec<Binding>>,
+    live_reload: bool,
+    // Prefixed to the URLs the page links to, when it is served under a path
+    // other than `/`.
+    base_path: String,
+    // Lets the page's event stream read its sources; see `SourceGrant`.
+    sources_token: Option<String>,
+    // Put on the page's scripts, for a Content Security Policy.
+    nonce: Option<String>,
+    // Where the page loads its bindings from, instead of an inline script.
+    bindings_url: Option<String>,
+    // The `roles` of each `x-path` the URL went through. Users need one role
+    // from each to see the page.
+    access: Vec<Vec<String>>,
+    // What each open bound element and `x-for` reads: a source, and where in
+    // its rows, like `rows[].` inside `x-for="row in data.rows"`. None when
+    // that isn't known.
+    scopes: Vec<Option<(ResourceName, String)>>,
+    // The fields the page's bindings read from each source.
+    reads: BTreeMap<ResourceName, BTreeSet<String>>,
 }
 
 impl Page {
-    fn push_part(&mut self, part: TemplatePart) {
-        if let TemplatePart::Binding(binding) = part {
-            self.bindings.push(binding.clone());
-            if let Some(source) = binding.source {
-                self.sources.insert(source);
+    // Reload the page when its templates change, and re-run its sources when
+    // only SQL files change.
+    pub fn with_live_reload(mut self, live_reload: bool) -> Self {
+        self.live_reload = live_reload;
+        self
+    }
+
+    pub fn with_base_path(mut self, base_path: &str) -> Self {
+        self.base_path = base_path.to_string();
+        self
+    }
+
+    pub fn with_sources_token(mut self, token: String) -> Self {
+        self.sources_token = Some(token);
+        self
+    }
+
+    pub fn with_nonce(mut self, nonce: &str) -> Self {
+        self.nonce = Some(nonce.to_string());
+        self
+    }
+
+    // Loads the page's `bindings_module` from `url`, a root-relative path the
+    // base path goes in front of.
+    pub fn with_bindings_url(mut self, url: String) -> Self {
+        self.bindings_url = Some(url);
+        self
+    }
+
+    fn nonce_attribute(&self) -> String {
+        match &self.nonce {
+            Some(nonce) => format!(r#" nonce="{}""#, escape_attribute(nonce)),
+            None => String::new(),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        match is_root_relative(path) {
+            true => format!("{}{}", self.base_path, path),
+            false => path.to_string(),
+        }
+    }
+
+    pub fn sources(&self) -> Vec<ResourceName> {
+        self.sources.iter().cloned().collect()
+    }
+
+    pub fn is_restricted(&self) -> bool {
+        !self.access.is_empty()
+    }
+
+    pub fn allows(&self, user: Option<&User>) -> bool {
+        self.access.iter().all(|roles| has_any_role(roles, user))
+    }
+
+    // The fields bindings read from each source, with `[]` after the fields
+    // whose items `x-for` rows are, as in `rows[].name`.
+    pub fn reads(&self) -> &BTreeMap<ResourceName, BTreeSet<String>> {
+        &self.reads
+    }
+
+    fn push_part(&mut self, part: TemplatePart) -> Result<()> {
+        // Row templates are only rendered in the browser, so bindings inside
+        // them are left out of the page's own parts.
+        let in_repeat = !self.repeats.is_empty();
+        match part {
+            TemplatePart::Binding(binding) => {
+                let scope = self.scope(&binding)?;
+                self.read(&scope, binding.dynamic.iter().flat_map(|d| &d.fields));
+                // Elements in rows all read the row.
+                if !in_repeat {
+                    self.scopes.push(scope);
+                    self.parts.push(TemplatePart::Binding(binding.clone()));
+                }
+                self.push_binding(binding)?;
+            }
+            TemplatePart::BoundContent | TemplatePart::BoundEnd if in_repeat => {}
+            TemplatePart::BoundEnd => {
+                self.scopes.pop();
+                self.parts.push(part);
+            }
+            _ => self.parts.push(part),
+        }
+        Ok(())
+    }
+
+    // A binding with a source reads its rows, and one without reads what the
+    // element it is in does.
+    fn scope(&self, binding: &Binding) -> Result<Option<(ResourceName, String)>> {
+        Ok(match &binding.source {
+            Some(source) => Some((ResourceName::parse(source)?, String::new())),
+            None => self.scopes.last().cloned().flatten(),
+        })
+    }
+
+    fn read<'a>(
+        &mut self,
+        scope: &Option<(ResourceName, String)>,
+        fields: impl Iterator<Item = &'a String>,
+    ) {
+        if let Some((source, prefix)) = scope {
+            let reads = self.reads.entry(source.clone()).or_default();
+            reads.extend(fields.map(|field| format!("{}{}", prefix, field)));
+        }
+    }
+
+    fn push_binding(&mut self, binding: Binding) -> Result<()> {
+        if let Some(source) = &binding.source {
+            self.sources.insert(ResourceName::parse(source)?);
+        }
+        match self.repeats.last_mut() {
+            Some(bindings) => bindings.push(binding),
+            None => self.bindings.push(binding),
+        }
+        Ok(())
+    }
+
+    fn open_repeat(&mut self, binding: Binding) -> Result<()> {
+        let scope = self.scope(&binding)?;
+        let each = binding.each.as_ref().expect("x-for without items");
+        self.read(&scope, each.fields.iter());
+        let row_scope = match (scope, &each.items_path) {
+            (Some((source, prefix)), Some(path)) => {
+                Some((source, format!("{}{}[].", prefix, path)))
             }
-        } else {
-            self.parts.push(part);
+            _ => None,
+        };
+        self.scopes.push(row_scope);
+        self.push_binding(binding)?;
+        self.repeats.push(Vec::new());
+        self.parts.push("<template data-bound>".into());
+        Ok(())
+    }
+
+    fn close_repeat(&mut self) {
+        let row_bindings = self.repeats.pop().expect("No open x-for");
+        self.scopes.pop();
+        self.parts.push("</template>".into());
+        let bindings = match self.repeats.last_mut() {
+            Some(bindings) => bindings,
+            None => &mut self.bindings,
+        };
+        if let Some(each) = bindings.last_mut().and_then(|b| b.each.as_mut()) {
+            each.bindings = row_bindings;
         }
     }
 
-    fn render(&self) -> String {
+    // With a dataset, bound values that can be evaluated on the server are
+    // rendered inline, and the dataset is handed to the preamble so the
+    // browser hydrates from it instead of opening the event stream.
+    pub fn render(&self, dataset: Option<&Dataset>) -> String {
         let mut html = String::new();
+        // The data each open bound element sees, and the bound text that
+        // replaces its content.
+        let mut bound: Vec<(Option<&Json>, Option<String>)> = Vec::new();
+        let mut skip_depth: Option<usize> = None;
         for part in &self.parts {
             match part {
-                TemplatePart::Content(s) => html.push_str(&s),
-                TemplatePart::HeadInjection => html.push_str(&self.head_injection()),
+                TemplatePart::Content(s) if skip_depth.is_none() => html.push_str(s),
+                TemplatePart::Content(_) => {}
+                TemplatePart::HeadInjection => html.push_str(&self.head_injection(dataset)),
                 TemplatePart::BodyInjection => html.push_str(&self.body_injection()),
+                TemplatePart::BasePath if skip_depth.is_none() => html.push_str(&self.base_path),
+                TemplatePart::Nonce if skip_depth.is_none() => {
+                    html.push_str(&self.nonce_attribute())
+                }
+                TemplatePart::BasePath | TemplatePart::Nonce => {}
+                TemplatePart::Binding(binding) => {
+                    let data = match (&binding.source, dataset) {
+                        (Some(source), Some(dataset)) => {
+                            dataset.get(source).and_then(|rows| rows.last())
+                        }
+                        (Some(_), None) => None,
+                        (None, _) => bound.last().and_then(|(data, _)| *data),
+                    };
+                    let mut content = None;
+                    if let Some(data) = data {
+                        if skip_depth.is_none() {
+                            content = render_binding(binding, data, &mut html);
+                        }
+                    }
+                    bound.push((data, content));
+                }
+                TemplatePart::BoundContent => {
+                    if let Some((_, Some(content))) = bound.last() {
+                        if skip_depth.is_none() {
+                            html.push_str(content);
+                            skip_depth = Some(bound.len());
+                        }
+                    }
+                }
+                TemplatePart::BoundEnd => {
+                    if skip_depth == Some(bound.len()) {
+                        skip_depth = None;
+                    }
+                    bound.pop();
+                }
                 _ => unreachable!(),
             }
         }
         html
     }
 
-    fn head_injection(&self) -> String {
-        let sources_json = serde_json::to_string(&self.sources).unwrap_or("[]".into());
+    fn head_injection(&self, dataset: Option<&Dataset>) -> String {
+        let sources_json = script_json(&self.sources, "[]");
+        let initial_json = match dataset {
+            Some(dataset) => script_json(dataset, "{}"),
+            None => "{}".to_string(),
+        };
+        let live_reload = match self.live_reload {
+            true => LIVE_RELOAD,
+            false => "",
+        };
         format!(
             r#"
-            <script>
+            <script{}>
+              const basePath = {}
               const sources = {}
+              const sourcesToken = {}
+              const initialData = {}
+              {}
               {}
             </script>
         "#,
-            sources_json, self.preamble,
+            self.nonce_attribute(),
+            base_path_json(&self.base_path),
+            sources_json,
+            script_json(&self.sources_token, "null"),
+            initial_json,
+            PREAMBLE,
+            live_reload,
         )
     }
 
-    fn body_injection(&self) -> String {
+    // The module that hands the page's bindings to the client runtime, with
+    // the modules they use.
+    pub fn bindings_module(&self) -> String {
         let mut modules = HashMap::new();
         let js_bindings: Vec<String> = self
             .bindings
             .iter()
-            .map(|binding| {
-                let mut js_binding = Vec::new();
-                if let Some(source) = &binding.source {
-                    js_binding.push(format!(r#"source:"{}""#, source));
-                }
-                if let Some(module_path) = &binding.module {
-                    let module_name = format!("m{}", modules.len());
-                    modules.insert(module_name.clone(), module_path);
-                    js_binding.push(format!(r#"module:{}"#, module_name));
-                }
-                js_binding.push(format!(r#"dynamic: {}"#, binding.dynamic));
-
-                format!(r#"{{ {} }}"#, js_binding.join(","))
-            })
+            .map(|binding| binding.to_js(&mut modules))
             .collect();
         let imports: Vec<String> = modules
             .iter()
-            .map(|(name, path)| format!(r#"import {} from "{}""#, name, path))
+            .map(|(name, path)| format!(r#"import {} from "{}""#, name, self.url(path)))
             .collect();
         format!(
             r#"
-        <script type="module">
-            import init from "/index.js"
+            import init from "{}"
             {}
 
             init(
             {}
             )
-        </script>
         "#,
+            self.url("/index.js"),
             imports.join("\n"),
             js_bindings.join(",\n")
         )
     }
+
+    fn body_injection(&self) -> String {
+        match &self.bindings_url {
+            Some(url) => format!(
+                r#"<script type="module"{} src="{}"></script>"#,
+                self.nonce_attribute(),
+                escape_attribute(&self.url(url))
+            ),
+            None => format!(
+                r#"
+        <script type="module"{}>{}</script>
+        "#,
+                self.nonce_attribute(),
+                self.bindings_module()
+            ),
+        }
+    }
+}
+
+// Writes the attributes a binding sets, and returns the content its text or
+// html binding would give the element. Expressions the server can't evaluate
+// are left for the browser.
+fn render_binding(binding: &Binding, data: &Json, html: &mut String) -> Option<String> {
+    let mut content = None;
+    for dynamic in &binding.dynamic {
+        let Some(value) = evaluate(&dynamic.expr, &binding.param, data) else {
+            continue;
+        };
+        match &dynamic.kind {
+            BindingKind::Text => content = to_display(&value).map(|s| escape_html(&s)),
+            BindingKind::Html => content = to_display(&value),
+            BindingKind::Value => {
+                if let Some(s) = to_display(&value) {
+                    html.push_str(&format!(r#" value="{}""#, escape_html(&s)));
+                }
+            }
+            BindingKind::Attr(name) => match value {
+                Json::Bool(false) | Json::Null => {}
+                Json::Bool(true) => html.push_str(&format!(" {}", name)),
+                value => {
+                    if let Some(s) = to_display(&value) {
+                        html.push_str(&format!(r#" {}="{}""#, name, escape_html(&s)));
+                    }
+                }
+            },
+            // These merge with the static attribute, so they wait for the browser.
+            BindingKind::Class(_) | BindingKind::Style(_) => {}
+        }
+    }
+    content
+}
+
+// Text and attribute values only need the characters that could end them
+// escaped; `escape_html` escapes for either.
+fn escape_text(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn escape_attribute(s: &str) -> String {
+    s.replace('&', "&amp;").replace('"', "&quot;")
+}
+
+pub fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
 }
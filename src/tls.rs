@@ -0,0 +1,142 @@
+use anyhow::{anyhow, Context, Result};
+use axum::{
+    http::{header, StatusCode, Uri},
+    response::{IntoResponse, Redirect, Response},
+    Router,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc::unbounded_channel;
+
+use crate::watch::DEBOUNCE;
+
+// Loads the certificate chain and private key, both PEM files, and reloads
+// them whenever either changes, so that a renewed certificate is served
+// without a restart. Connections already open keep the certificate they
+// started with. A reload that fails, like one that reads a half-written file,
+// keeps the previous certificate until the next change.
+pub async fn rustls_config(cert: &Path, key: &Path) -> Result<RustlsConfig> {
+    let (cert_pem, key_pem) = read_pem_files(cert, key).await?;
+    let config = RustlsConfig::from_pem(cert_pem, key_pem)
+        .await
+        .with_context(|| format!("Failed to load certificate {}", cert.display()))?;
+
+    // Renewals usually replace the files, or the symlinks to them, rather
+    // than writing to them, which ends a watch on the file itself. Watching
+    // their directories sees the replacement too. Events report paths under
+    // the watched path as given, so watch the canonical directories.
+    let files = [watched_path(cert)?, watched_path(key)?];
+    let directories: HashSet<&Path> = files.iter().filter_map(|file| file.parent()).collect();
+    let (tx, mut rx) = unbounded_channel();
+    let watched = files.clone();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| match res {
+        Ok(event) => {
+            if !matches!(event.kind, EventKind::Access(_))
+                && event.paths.iter().any(|path| watched.contains(path))
+            {
+                let _ = tx.send(());
+            }
+        }
+        Err(e) => eprintln!("Watch error: {}", e),
+    })?;
+    for directory in directories {
+        watcher.watch(directory, RecursiveMode::NonRecursive)?;
+    }
+
+    let reloaded = config.clone();
+    let (cert, key) = (cert.to_path_buf(), key.to_path_buf());
+    tokio::spawn(async move {
+        // The watcher lasts as long as the task.
+        let _watcher = watcher;
+        while rx.recv().await.is_some() {
+            tokio::time::sleep(DEBOUNCE).await;
+            while rx.try_recv().is_ok() {}
+            let result = match read_pem_files(&cert, &key).await {
+                Ok((cert_pem, key_pem)) => reloaded
+                    .reload_from_pem(cert_pem, key_pem)
+                    .await
+                    .map_err(anyhow::Error::from),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => println!("Reloaded certificate {}", cert.display()),
+                Err(e) => eprintln!("Failed to reload certificate: {:#}", e),
+            }
+        }
+    });
+    Ok(config)
+}
+
+// Reads the certificate chain and key, checking that each has what it should,
+// since a chain with no certificates would load, and fail every handshake.
+async fn read_pem_files(cert: &Path, key: &Path) -> Result<(Vec<u8>, Vec<u8>)> {
+    let cert_pem = tokio::fs::read(cert)
+        .await
+        .with_context(|| format!("Failed to read certificate {}", cert.display()))?;
+    let key_pem = tokio::fs::read(key)
+        .await
+        .with_context(|| format!("Failed to read key {}", key.display()))?;
+    let certs = rustls_pemfile::certs(&mut cert_pem.as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Invalid certificate {}", cert.display()))?;
+    if certs.is_empty() {
+        return Err(anyhow!("No certificates in {}", cert.display()));
+    }
+    rustls_pemfile::private_key(&mut key_pem.as_slice())
+        .with_context(|| format!("Invalid key {}", key.display()))?
+        .ok_or_else(|| anyhow!("No private key in {}", key.display()))?;
+    Ok((cert_pem, key_pem))
+}
+
+// The file in its canonical directory. The file itself isn't resolved, since a
+// symlink to it may be what a renewal replaces.
+fn watched_path(file: &Path) -> Result<PathBuf> {
+    let name = file
+        .file_name()
+        .ok_or_else(|| anyhow!("{} isn't a file", file.display()))?;
+    let directory = match file.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let directory = directory
+        .canonicalize()
+        .with_context(|| format!("Failed to watch {}", directory.display()))?;
+    Ok(directory.join(name))
+}
+
+// Answers every plain HTTP request with a permanent redirect to the same
+// host, path and query over HTTPS, on `https_port`.
+pub fn redirect_router(https_port: u16) -> Router {
+    Router::new().fallback(move |headers: header::HeaderMap, uri: Uri| async move {
+        redirect_to_https(&headers, &uri, https_port)
+    })
+}
+
+fn redirect_to_https(headers: &header::HeaderMap, uri: &Uri, https_port: u16) -> Response {
+    let Some(host) = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .map(strip_port)
+    else {
+        return (StatusCode::BAD_REQUEST, "Missing Host header").into_response();
+    };
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+    let location = match https_port {
+        443 => format!("https://{}{}", host, path),
+        port => format!("https://{}:{}{}", host, port, path),
+    };
+    match location.parse::<Uri>() {
+        Ok(_) => Redirect::permanent(&location).into_response(),
+        Err(_) => (StatusCode::BAD_REQUEST, "Invalid Host header").into_response(),
+    }
+}
+
+// `example.com:80` is `example.com`, and `[::1]:80` is `[::1]`.
+fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port)) if !name.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => name,
+        _ => host,
+    }
+}
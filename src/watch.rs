@@ -0,0 +1,174 @@
+use anyhow::{anyhow, Result};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+use crate::cache::is_ignored;
+use crate::AppState;
+
+// Sent to live-reloading pages after a successful rebuild.
+#[derive(Debug, Clone, Copy)]
+pub enum Reload {
+    Templates,
+    Statements,
+}
+
+impl Reload {
+    pub fn event_name(&self) -> &'static str {
+        match self {
+            Reload::Templates => "templates",
+            Reload::Statements => "sql",
+        }
+    }
+}
+
+// Events that arrive within this window of each other are handled as one
+// change, so that an editor's save or a checkout only triggers one rebuild.
+pub(crate) const DEBOUNCE: Duration = Duration::from_millis(50);
+
+// Watches the template and SQL directories and rebuilds the collections when
+// their files change. Rebuilds happen off the request path: a copy of the
+// collection recompiles the changed files without holding any lock, and is
+// then swapped in. The watcher is owned by the rebuilding task, so watching
+// lasts as long as the runtime.
+pub async fn watch(state: AppState) -> Result<()> {
+    let (tx, rx) = unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| match res {
+        Ok(event) => {
+            let _ = tx.send(event);
+        }
+        Err(e) => eprintln!("Watch error: {}", e),
+    })?;
+    // Events report paths under the watched path as given, so watch the
+    // canonical paths to compare against.
+    let template_dir = watched_directory(state.templates.read().await.directory())?;
+    let sql_dir = watched_directory(state.statements.read().await.directory())?;
+    watcher.watch(&template_dir, RecursiveMode::Recursive)?;
+    watcher.watch(&sql_dir, RecursiveMode::Recursive)?;
+    tokio::spawn(rebuild_on_change(watcher, state, rx, template_dir, sql_dir));
+    Ok(())
+}
+
+fn watched_directory(directory: Option<&Path>) -> Result<PathBuf> {
+    let directory = directory.ok_or_else(|| anyhow!("Bundled files can't be watched"))?;
+    Ok(directory.canonicalize()?)
+}
+
+async fn rebuild_on_change(
+    _watcher: RecommendedWatcher,
+    state: AppState,
+    mut rx: UnboundedReceiver<Event>,
+    template_dir: PathBuf,
+    sql_dir: PathBuf,
+) {
+    while let Some(event) = rx.recv().await {
+        let mut paths = event_paths(event);
+        tokio::time::sleep(DEBOUNCE).await;
+        while let Ok(event) = rx.try_recv() {
+            paths.extend(event_paths(event));
+        }
+        // Failures are recorded in the state, and retried on the next change.
+        if paths.iter().any(|path| path.starts_with(&template_dir)) {
+            let _ = rebuild_templates(&state).await;
+        }
+        if paths.iter().any(|path| path.starts_with(&sql_dir)) {
+            let _ = rebuild_statements(&state).await;
+        }
+    }
+}
+
+// The paths an event touches, leaving out reads and editor swap files.
+fn event_paths(event: Event) -> Vec<PathBuf> {
+    if matches!(event.kind, EventKind::Access(_)) {
+        return Vec::new();
+    }
+    event
+        .paths
+        .into_iter()
+        .filter(|path| {
+            !path
+                .file_name()
+                .is_some_and(|name| is_ignored(&name.to_string_lossy()))
+        })
+        .collect()
+}
+
+// Rebuilds are transactional: the changed files are compiled into a copy of
+// the collection, which only replaces the one being served if every file
+// compiled. A failed file is compiled again on the next rebuild, since its
+// change was never committed.
+pub async fn rebuild_templates(state: &AppState) -> Result<()> {
+    let mut templates = state.templates.read().await.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        templates.recompile().map(|changed| (templates, changed))
+    })
+    .await
+    .unwrap_or_else(|e| Err(anyhow!("Template compilation panicked: {}", e)));
+    let result = match result {
+        Ok((templates, true)) => {
+            *state.templates.write().await = templates;
+            Ok(true)
+        }
+        Ok((_, false)) => Ok(false),
+        Err(e) => Err(e),
+    };
+    record(state, Reload::Templates, result).await
+}
+
+pub async fn rebuild_statements(state: &AppState) -> Result<()> {
+    let mut statements = state.statements.read().await.clone();
+    let result = statements.recompile(state.client_pool.clone()).await;
+    if let Ok(true) = result {
+        *state.statements.write().await = statements;
+    }
+    record(state, Reload::Statements, result).await
+}
+
+// The last failed rebuild of each collection, until one succeeds.
+#[derive(Debug, Default)]
+pub struct BuildErrors {
+    templates: Option<String>,
+    statements: Option<String>,
+}
+
+impl BuildErrors {
+    pub fn message(&self) -> Option<String> {
+        let errors: Vec<&str> = [&self.templates, &self.statements]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        match errors.is_empty() {
+            true => None,
+            false => Some(errors.join("\n\n")),
+        }
+    }
+}
+
+// Logs a failed rebuild and keeps it for the dev error page. Open pages reload
+// fully when an error appears or clears, so that they show or leave the error
+// page.
+async fn record(state: &AppState, reload: Reload, result: Result<bool>) -> Result<()> {
+    let mut errors = state.build_errors.write().await;
+    let error = match reload {
+        Reload::Templates => &mut errors.templates,
+        Reload::Statements => &mut errors.statements,
+    };
+    match result {
+        Ok(changed) => {
+            if error.take().is_some() {
+                let _ = state.reloads.send(Reload::Templates);
+            } else if changed {
+                let _ = state.reloads.send(reload);
+            }
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("Rebuild failed: {:#}", e);
+            *error = Some(format!("{:#}", e));
+            let _ = state.reloads.send(Reload::Templates);
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn project(name: &str, templates: &[(&str, &str)]) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("webware-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    for (file, contents) in templates {
+        let path = root.join("src/templates").join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    fs::create_dir_all(root.join("src/sql")).unwrap();
+    root
+}
+
+#[test]
+fn check_reports_a_template_that_embeds_itself() {
+    let root = project(
+        "check-cycle",
+        &[
+            ("index.html", r#"<x-embed file="loop.html"/>"#),
+            ("loop.html", r#"<p><x-embed file="loop.html"/></p>"#),
+        ],
+    );
+    let output = Command::new(env!("CARGO_BIN_EXE_webware"))
+        .args(["check", "--offline", "--project"])
+        .arg(&root)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // 1 is the exit code for a project with errors.
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    assert!(
+        stderr.contains("/ doesn't build: x-embed/x-layout cycle: loop.html → loop.html"),
+        "{}",
+        stderr
+    );
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn check_passes_a_project_without_problems() {
+    let root = project("check-ok", &[("index.html", "<p>hello</p>")]);
+    let output = Command::new(env!("CARGO_BIN_EXE_webware"))
+        .args(["check", "--offline", "--project"])
+        .arg(&root)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("No problems found"));
+    fs::remove_dir_all(root).unwrap();
+}
@@ -0,0 +1,81 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use webware::template::TemplateCollection;
+
+fn project(name: &str, templates: &[(&str, &str)]) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("webware-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    for (file, contents) in templates {
+        let path = root.join("src/templates").join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    root
+}
+
+fn page_error(root: &Path, url: &str) -> String {
+    let mut templates = TemplateCollection::new(root.join("src/templates"), false);
+    templates.recompile().unwrap();
+    match templates.build_page(url.to_string()) {
+        Ok(_) => panic!("{} built", url),
+        Err(e) => format!("{:#}", e),
+    }
+}
+
+#[test]
+fn embed_cycles_are_errors() {
+    let root = project(
+        "embed-cycle",
+        &[
+            ("index.html", r#"<x-embed file="a.html"/>"#),
+            ("a.html", r#"<p><x-embed file="b.html"/></p>"#),
+            ("b.html", r#"<p><x-embed file="a.html"/></p>"#),
+        ],
+    );
+    assert!(page_error(&root, "/").contains("x-embed/x-layout cycle: a.html → b.html → a.html"));
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn layout_cycles_are_errors() {
+    let root = project(
+        "layout-cycle",
+        &[(
+            "index.html",
+            r#"<x-layout file="index.html"><p slot="main">hi</p></x-layout>"#,
+        )],
+    );
+    assert!(page_error(&root, "/").contains("x-embed/x-layout cycle: index.html → index.html"));
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn routes_that_match_nothing_cant_loop() {
+    let root = project(
+        "route-cycle",
+        &[(
+            "index.html",
+            r#"<x-route><x-path url="" file="index.html"/></x-route>"#,
+        )],
+    );
+    assert!(page_error(&root, "/").contains("x-route cycle: index.html → index.html"));
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn the_same_embed_twice_is_not_a_cycle() {
+    let root = project(
+        "embed-twice",
+        &[
+            (
+                "index.html",
+                r#"<x-embed file="card.html"/><x-embed file="card.html"/>"#,
+            ),
+            ("card.html", "<p>card</p>"),
+        ],
+    );
+    let mut templates = TemplateCollection::new(root.join("src/templates"), false);
+    templates.recompile().unwrap();
+    assert!(templates.build_page("/".to_string()).is_ok());
+    fs::remove_dir_all(root).unwrap();
+}
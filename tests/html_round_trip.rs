@@ -0,0 +1,213 @@
+use html5gum::{DefaultEmitter, Token, Tokenizer};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use webware::template::TemplateCollection;
+
+// Templates whose text and attributes the compiler has to write back so that
+// a browser reads them exactly as the template's author wrote them.
+const CASES: &[(&str, &str)] = &[
+    (
+        "escaped_markup",
+        "<p>&lt;script&gt;alert(1)&lt;/script&gt;</p>",
+    ),
+    (
+        "numeric_refs",
+        "<p>&#x3C;img src=x onerror=alert(1)&#x3E; &#60;b&#62;</p>",
+    ),
+    (
+        "named_refs",
+        "<p>Tom &amp; Jerry &copy; 2024 &mdash; a&nbsp;b</p>",
+    ),
+    ("bare_ampersand", "<p>fish & chips, R&D &</p>"),
+    ("gt_in_text", "<p>a > b</p>"),
+    ("double_quotes", r#"<a title="say &quot;hi&quot;">x</a>"#),
+    ("single_quotes", r#"<a title='single "double" &#39;'>x</a>"#),
+    ("unquoted", "<input value=a&amp;b&lt;c>"),
+    (
+        "markup_in_attribute",
+        r#"<img alt="&lt;img src=x onerror=alert(1)&gt;">"#,
+    ),
+    (
+        "quote_breakout",
+        r#"<div data-x='" onmouseover="alert(1)'>x</div>"#,
+    ),
+    (
+        "root_relative_url",
+        r#"<a href="/x?a=1&amp;b=&quot;2&quot;">x</a>"#,
+    ),
+    (
+        "ampersand_in_url",
+        r#"<a href="https://example.com/?a=1&b=2">x</a>"#,
+    ),
+    ("boolean_attribute", "<input disabled><input value=\"\">"),
+    (
+        "script",
+        r#"<script>if (a < b && c > d) { x = "</div>&amp;" }</script>"#,
+    ),
+    (
+        "script_comment_like",
+        "<script>const s = '<!-- <p>not markup</p> -->'</script>",
+    ),
+    (
+        "style",
+        r#"<style>p > a::after { content: "&amp; <b>" }</style>"#,
+    ),
+    ("textarea", "<textarea>&lt;b&gt; &amp; </p></textarea>"),
+    ("title", "<title>A &lt; B &amp; C</title>"),
+    ("noscript", "<noscript><p>&amp;</p></noscript>"),
+    (
+        "nested",
+        r#"<ul><li class="a&amp;b">1 &lt; 2</li><li>&quot;q&quot;</li></ul>"#,
+    ),
+    (
+        "unicode",
+        "<p title=\"caf\u{e9} \u{1f600}\">\u{e9}t\u{e9} &#x1F600;</p>",
+    ),
+];
+
+fn tokens(html: &str) -> Vec<String> {
+    let mut emitter = DefaultEmitter::default();
+    emitter.switch_states(true);
+    let mut tokens: Vec<String> = Vec::new();
+    let mut text = String::new();
+    for token in Tokenizer::new_with_emitter(html, emitter).flatten() {
+        let token = match token {
+            Token::String(s) => {
+                text.push_str(&String::from_utf8(s.0).unwrap());
+                continue;
+            }
+            Token::StartTag(tag) => {
+                let attributes: BTreeMap<String, String> = tag
+                    .attributes
+                    .into_iter()
+                    .map(|(k, v)| {
+                        (
+                            String::from_utf8(k.0).unwrap(),
+                            String::from_utf8(v.0).unwrap(),
+                        )
+                    })
+                    .collect();
+                format!(
+                    "start {} {:?}",
+                    String::from_utf8(tag.name.0).unwrap(),
+                    attributes
+                )
+            }
+            Token::EndTag(tag) => format!("end {}", String::from_utf8(tag.name.0).unwrap()),
+            Token::Doctype(doctype) => {
+                format!("doctype {}", String::from_utf8(doctype.name.0).unwrap())
+            }
+            Token::Comment(_) | Token::Error(_) => continue,
+        };
+        if !text.is_empty() {
+            tokens.push(format!("text {:?}", std::mem::take(&mut text)));
+        }
+        tokens.push(token);
+    }
+    if !text.is_empty() {
+        tokens.push(format!("text {:?}", text));
+    }
+    tokens
+}
+
+fn compile(name: &str, files: &[(&str, &str)]) -> (PathBuf, TemplateCollection) {
+    let dir = std::env::temp_dir().join(format!("webware-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for (file, contents) in files {
+        fs::write(dir.join(file), contents).unwrap();
+    }
+    let mut templates = TemplateCollection::new(dir.clone(), false);
+    templates.recompile().unwrap();
+    (dir, templates)
+}
+
+fn render(templates: &TemplateCollection, file: &str, props: &[(&str, &str)]) -> String {
+    let props = props
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    templates
+        .build_template_page(file, &props)
+        .unwrap()
+        .render(None)
+}
+
+#[test]
+fn text_and_attributes_round_trip() {
+    let files: Vec<(String, &str)> = CASES
+        .iter()
+        .map(|(name, html)| (format!("{}.html", name), *html))
+        .collect();
+    let files: Vec<(&str, &str)> = files
+        .iter()
+        .map(|(file, html)| (file.as_str(), *html))
+        .collect();
+    let (dir, templates) = compile("round-trip", &files);
+    for (file, html) in files {
+        let rendered = render(&templates, file, &[]);
+        assert_eq!(
+            tokens(&rendered),
+            tokens(html),
+            "{} rendered as {}",
+            file,
+            rendered
+        );
+        // Written back once, the output is stable.
+        let (again_dir, again) = compile("round-trip-again", &[("page.html", &rendered)]);
+        assert_eq!(render(&again, "page.html", &[]), rendered, "{}", file);
+        fs::remove_dir_all(again_dir).unwrap();
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn entities_stay_escaped() {
+    let (dir, templates) = compile("entities", &[("page.html", CASES[0].1)]);
+    let rendered = render(&templates, "page.html", &[]);
+    assert!(!rendered.contains("<script>"), "{}", rendered);
+    assert!(rendered.contains("&lt;script&gt;"), "{}", rendered);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn prop_values_are_escaped_except_in_raw_text() {
+    let (dir, templates) = compile(
+        "props",
+        &[(
+            "card.html",
+            r#"<x-props title="" note=""/><h2 title="{{title}}">{{title}}</h2><script>const note = "{{note}}"</script>"#,
+        )],
+    );
+    let value = r#""><img src=x onerror=alert(1)> & <b>"#;
+    let rendered = render(
+        &templates,
+        "card.html",
+        &[("title", value), ("note", "a & <b>")],
+    );
+    let expected = tokens(&format!(
+        r#"<h2 title="{0}">{0}</h2><script>const note = "a & <b>"</script>"#,
+        "&quot;&gt;&lt;img src=x onerror=alert(1)&gt; &amp; &lt;b&gt;"
+    ));
+    assert_eq!(tokens(&rendered), expected, "{}", rendered);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn embedded_props_are_decoded_once() {
+    let (dir, templates) = compile(
+        "embeds",
+        &[
+            (
+                "page.html",
+                r#"<x-embed file="card.html" title="Tom &amp; Jerry &lt;3"/>"#,
+            ),
+            ("card.html", "<p>{{title}}</p>"),
+        ],
+    );
+    let rendered = render(&templates, "page.html", &[]);
+    assert_eq!(tokens(&rendered), tokens("<p>Tom &amp; Jerry &lt;3</p>"));
+    assert!(rendered.contains("Tom &amp; Jerry &lt;3"), "{}", rendered);
+    fs::remove_dir_all(dir).unwrap();
+}
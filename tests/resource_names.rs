@@ -0,0 +1,230 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use deadpool_postgres::{Config, Runtime};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio_postgres::NoTls;
+use tower::ServiceExt;
+use webware::bundle::Bundle;
+use webware::resource::ResourceName;
+use webware::sql::{Source, StatementCollection};
+use webware::template::TemplateCollection;
+use webware::{Project, Webware};
+
+// A project directory under the system temp directory, with `secret.html`
+// and `secret.sql` beside the template and SQL directories, where no name
+// should reach them.
+fn project(name: &str, templates: &[(&str, &str)]) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("webware-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    for (file, contents) in templates {
+        let path = root.join("src/templates").join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    fs::create_dir_all(root.join("src/sql")).unwrap();
+    fs::write(root.join("src/secret.html"), "<p>secret</p>").unwrap();
+    fs::write(root.join("src/secret.sql"), "SELECT 'secret'").unwrap();
+    root
+}
+
+fn compile(root: &Path) -> TemplateCollection {
+    let mut templates = TemplateCollection::new(root.join("src/templates"), false);
+    templates.recompile().unwrap();
+    templates
+}
+
+fn page_error(templates: &TemplateCollection, url: &str) -> String {
+    match templates.build_page(url.to_string()) {
+        Ok(_) => panic!("{} built", url),
+        Err(e) => format!("{:#}", e),
+    }
+}
+
+#[test]
+fn accepts_names_inside_the_directory() {
+    for name in [
+        "index.html",
+        "now.sql",
+        "components/card.html",
+        "a/b/c.d.sql",
+    ] {
+        assert_eq!(ResourceName::parse(name).unwrap().as_str(), name);
+    }
+}
+
+#[test]
+fn rejects_names_that_could_leave_the_directory() {
+    let names = [
+        "",
+        "..",
+        ".",
+        "../secret.sql",
+        "sql/../../secret.sql",
+        "./index.html",
+        "a//b.html",
+        "a/",
+        "/etc/passwd",
+        "..\\secret.sql",
+        "C:secret.sql",
+        "c:\\windows\\win.ini",
+        "%2e%2e/secret.sql",
+        "..%2fsecret.sql",
+        "%252e%252e%252fsecret.sql",
+        "secret.sql\0.html",
+        "secret\n.sql",
+    ];
+    for name in names {
+        assert!(ResourceName::parse(name).is_err(), "accepted {:?}", name);
+    }
+}
+
+#[test]
+fn names_deserialize_only_when_valid() {
+    let name: ResourceName = serde_json::from_str(r#""components/card.html""#).unwrap();
+    assert_eq!(name.as_str(), "components/card.html");
+    assert!(serde_json::from_str::<ResourceName>(r#""../secret.sql""#).is_err());
+}
+
+#[test]
+fn embeds_and_layouts_stay_in_the_template_directory() {
+    let root = project(
+        "embeds",
+        &[
+            ("index.html", r#"<x-embed file="components/card.html"/>"#),
+            ("components/card.html", "<p>card</p>"),
+            ("embed.html", r#"<x-embed file="../secret.html"/>"#),
+            (
+                "layout.html",
+                r#"<x-layout file="../secret.html"></x-layout>"#,
+            ),
+            ("absolute.html", r#"<x-embed file="/etc/passwd"/>"#),
+        ],
+    );
+    let templates = compile(&root);
+    let html = templates.build_page("/".to_string()).unwrap().render(None);
+    assert!(html.contains("<p>card</p>"));
+    for file in ["embed.html", "layout.html", "absolute.html"] {
+        let error = match templates.build_template_page(file, &Default::default()) {
+            Ok(page) => panic!("{} built: {}", file, page.render(None)),
+            Err(e) => format!("{:#}", e),
+        };
+        assert!(error.contains("Invalid file name"), "{}: {}", file, error);
+    }
+    assert!(templates
+        .build_template_page("../secret.html", &Default::default())
+        .is_err());
+    assert!(!templates.contains("../secret.html"));
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn routes_stay_in_the_template_directory() {
+    let root = project(
+        "routes",
+        &[(
+            "index.html",
+            r#"<x-route>
+                 <x-path url="" file="home.html"/>
+                 <x-path url="secret" file="../secret.html"/>
+               </x-route>"#,
+        )],
+    );
+    let templates = compile(&root);
+    assert!(page_error(&templates, "/secret").contains("Invalid file name"));
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn sources_stay_in_the_sql_directory() {
+    let root = project(
+        "sources",
+        &[(
+            "index.html",
+            r#"<p x-source="../secret.sql" x-text="data"></p>"#,
+        )],
+    );
+    let templates = compile(&root);
+    assert!(page_error(&templates, "/").contains("Invalid file name"));
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn the_api_refuses_names_outside_the_sql_directory() {
+    let root = project("api", &[("index.html", "<p>index</p>")]);
+    let templates = compile(&root);
+    // Even a collection holding the name can't be asked for it.
+    let statements = StatementCollection::from_bundle(HashMap::from([(
+        "../secret.sql".to_string(),
+        Source::parse("SELECT 'secret'").unwrap(),
+    )]));
+    // The pool connects lazily, and nothing here reaches the database.
+    let config = Config {
+        dbname: Some("webware".to_string()),
+        ..Default::default()
+    };
+    let pool = config.create_pool(Some(Runtime::Tokio1), NoTls).unwrap();
+    let app = Webware::new(Project::new(&root), pool)
+        .bundle(Bundle::new(&templates, &statements))
+        .router()
+        .await
+        .unwrap();
+    let queries = [
+        "source=../secret.sql",
+        "source=%2e%2e%2fsecret.sql",
+        "source=%252e%252e%252fsecret.sql",
+        "source=..%5Csecret.sql",
+        "source=%2Fetc%2Fpasswd",
+        "source=now.sql&source=sql/../../secret.sql",
+    ];
+    for query in queries {
+        let request = Request::get(format!("/api?{}", query))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", query);
+    }
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn export_stays_in_the_output_directory() {
+    let root = project(
+        "export",
+        &[
+            (
+                "index.html",
+                r#"<x-route>
+                 <x-path url="" file="home.html"/>
+                 <x-path url=".." file="home.html"/>
+               </x-route>"#,
+            ),
+            ("home.html", "<p>home</p>"),
+        ],
+    );
+    fs::create_dir_all(root.join("www")).unwrap();
+    let templates = compile(&root);
+    let statements = StatementCollection::from_bundle(HashMap::new());
+    let config = Config {
+        dbname: Some("webware".to_string()),
+        ..Default::default()
+    };
+    let pool = config.create_pool(Some(Runtime::Tokio1), NoTls).unwrap();
+    let out = root.join("out");
+    let result = webware::export::export(
+        &templates,
+        &statements,
+        Arc::new(pool),
+        &root.join("www"),
+        &out,
+        "",
+        false,
+    )
+    .await;
+    assert!(result.is_err());
+    assert!(out.join("index.html").exists());
+    assert!(!root.join("index.html").exists());
+    fs::remove_dir_all(root).unwrap();
+}
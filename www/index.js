@@ -5,6 +5,10 @@ class DataNotFoundError extends Error {
     this.name = 'DataNotFoundError';
   }
 }
+function isObject(value) {
+  return typeof value === "object" && value !== null;
+}
+
 class BindTree {
   constructor(node, bindings = {}) {
     this.node = node;
@@ -12,6 +16,7 @@ class BindTree {
     this.data = null;
     this.children = [];
     this.source = null;
+    this.rows = new Map();
     if (bindings.module) {
       const source = bindings.source ? new AsyncStream() : null;
       bindings.module({ node, source });
@@ -32,18 +37,24 @@ class BindTree {
     }
   }
   bind(data) {
-    const dataProxy = new Proxy(data, {
-      get(target, prop, receiver) {
-        if (!(prop in target)) {
-          throw new DataNotFoundError(prop);
-        }
-        return Reflect.get(target, prop, receiver);
-      },
-    });
-    for (const [bind, fn] of Object.entries(this.bindings.dynamic || {})) {
+    if (this.bindings.each) {
+      this.bindEach(data);
+      return;
+    }
+    const dataProxy = isObject(data)
+      ? new Proxy(data, {
+          get(target, prop, receiver) {
+            if (!(prop in target)) {
+              throw new DataNotFoundError(prop);
+            }
+            return Reflect.get(target, prop, receiver);
+          },
+        })
+      : data;
+    for (const binding of this.bindings.dynamic || []) {
       let value;
       try {
-        value = fn(dataProxy);
+        value = binding.fn(dataProxy);
       } catch (e) {
         if (e.name === 'DataNotFoundError') {
           // Intentionally ignored
@@ -53,7 +64,7 @@ class BindTree {
         }
       }
       if (value !== undefined) {
-        this.applyBinding(bind, value);
+        this.applyBinding(binding, value);
       }
     }
     for (const child of this.children) {
@@ -63,13 +74,73 @@ class BindTree {
   inheritBinding(data) {
     if (!this.bindings.source) this.bind(data);
   }
-  applyBinding(name, value) {
-    switch (name) {
+  bindEach(data) {
+    const { items, key, bindings } = this.bindings.each;
+    let value;
+    try {
+      value = items(data);
+    } catch (e) {
+      if (e.name !== 'DataNotFoundError') {
+        console.error("Bound list error", e);
+      }
+      return;
+    }
+    for (const row of Array.isArray(value) ? value : [value]) {
+      // Unkeyed rows are always appended; keyed rows are updated in place.
+      const rowKey = key ? key(row) : this.rows.size;
+      let rowTree = this.rows.get(rowKey);
+      if (!rowTree) {
+        rowTree = this.createRow(bindings);
+        this.rows.set(rowKey, rowTree);
+      }
+      rowTree.bind(row);
+    }
+  }
+  createRow(bindings) {
+    const fragment = this.node.content.cloneNode(true);
+    const rowTree = new BindTree(fragment);
+    bindElements(fragment, rowTree, bindings);
+    rowTree.rowNodes = [...fragment.childNodes];
+    this.node.parentNode.insertBefore(fragment, this.node);
+    return rowTree;
+  }
+  // Unkeyed rows are only ever appended, so they are removed before the
+  // source is run again. Keyed rows are updated in place.
+  clearRows() {
+    if (!this.bindings.each || this.bindings.each.key) return;
+    for (const rowTree of this.rows.values()) {
+      rowTree.rowNodes.forEach((node) => node.remove());
+    }
+    this.rows.clear();
+  }
+  applyBinding({ kind, name }, value) {
+    switch (kind) {
       case "text":
         this.node.textContent = value;
         break;
-      default:
-        throw new Error("Unrecognized binding", name);
+      case "html":
+        this.node.innerHTML = value;
+        break;
+      case "value":
+        this.node.value = value;
+        break;
+      case "attr":
+        if (value === false || value === null) {
+          this.node.removeAttribute(name);
+        } else {
+          this.node.setAttribute(name, value === true ? "" : value);
+        }
+        break;
+      case "class":
+        this.node.classList.toggle(name, !!value);
+        break;
+      case "style":
+        if (value === false || value === null) {
+          this.node.style.removeProperty(name);
+        } else {
+          this.node.style.setProperty(name, value);
+        }
+        break;
     }
   }
 }
@@ -89,6 +160,24 @@ function init(bindTree) {
     processEventStream(eventStream, treeNodes);
   }
   console.log("bT", bindTree);
+
+  // Re-runs the page's sources in place, e.g. after a SQL file changed.
+  // Modules consume their source once, so pages with modules reload instead.
+  window.refreshSources = () => {
+    let hasModules = false;
+    bindTree.visit((treeNode) => {
+      hasModules ||= !!treeNode.bindings.module;
+      treeNode.clearRows();
+    });
+    if (hasModules) {
+      location.reload();
+      return;
+    }
+    const streams = window.connectSources([...sourceBindings.keys()], {});
+    for (const [source, treeNodes] of sourceBindings) {
+      processEventStream(streams[source], treeNodes);
+    }
+  };
 }
 
 async function processEventStream(eventStream, treeNodes) {
@@ -113,9 +202,9 @@ function findParent(bindSet, node) {
   }
 }
 
-export default function (...bindings) {
+function bindElements(root, rootTree, bindings) {
   const walker = document.createTreeWalker(
-    document.body, // root
+    root, // root
     NodeFilter.SHOW_ELEMENT, // filter
     {
       acceptNode: (node) =>
@@ -126,16 +215,20 @@ export default function (...bindings) {
     false,
   );
   const bindMap = new Map();
-  const bindTree = new BindTree(document.body);
   let node,
     i = 0;
   do {
     node = walker.nextNode();
     if (node) {
-      const boundParent = findParent(bindMap, node) || bindTree;
+      const boundParent = findParent(bindMap, node) || rootTree;
       const binding = boundParent.addChild(node, bindings[i++]);
       bindMap.set(node, binding);
     }
   } while (node);
+}
+
+export default function (...bindings) {
+  const bindTree = new BindTree(document.body);
+  bindElements(document.body, bindTree, bindings);
   init(bindTree);
 }
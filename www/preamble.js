@@ -1,11 +1,3 @@
-const queryParams = sources
-  .map((str) => `source=${encodeURIComponent(str)}`)
-  .join("&");
-const eventSource = new EventSource("/api?" + queryParams);
-eventSource.addEventListener("stream_stop", (e) => {
-  eventSource.close();
-});
-
 class AsyncStream {
   constructor() {
     this.resolver = null;
@@ -41,18 +33,66 @@ class AsyncStream {
   }
 }
 
-window.apiEventSource = Object.fromEntries(
-  sources.map((source) => {
-    const stream = new AsyncStream();
-    eventSource.addEventListener(source, (e) => {
-      stream.push(JSON.parse(e.data));
-    });
-    eventSource.addEventListener("stream_stop", () => {
-      stream.close();
-    });
-    return [source, stream];
-  }),
-);
+async function reloadWhenUp() {
+  for (;;) {
+    await new Promise((resolve) => setTimeout(resolve, 1000));
+    try {
+      await fetch(location.href, { method: "HEAD", cache: "no-store" });
+      location.reload();
+      return;
+    } catch {
+      // Not back yet.
+    }
+  }
+}
+
+// Opens the event stream for the sources and returns an AsyncStream per
+// source. Sources rendered on the server arrive in `initial`; only the rest
+// are streamed.
+function connectSources(sources, initial) {
+  const liveSources = sources.filter((source) => !(source in initial));
+  const queryParams = liveSources
+    .map((str) => `source=${encodeURIComponent(str)}`)
+    .concat(sourcesToken ? [`token=${encodeURIComponent(sourcesToken)}`] : [])
+    .join("&");
+  const eventSource = liveSources.length
+    ? new EventSource(basePath + "/api?" + queryParams)
+    : null;
+  eventSource?.addEventListener("stream_stop", (e) => {
+    eventSource.close();
+  });
+  // The server shut down before the sources finished. The page's token may
+  // not outlast it, so reload the page once the server is back, rather than
+  // keep what arrived.
+  eventSource?.addEventListener("reconnect", () => {
+    eventSource.close();
+    reloadWhenUp();
+  });
+
+  return Object.fromEntries(
+    sources.map((source) => {
+      const stream = new AsyncStream();
+      if (source in initial) {
+        initial[source].forEach((data) => stream.push(data));
+        stream.close();
+        return [source, stream];
+      }
+      eventSource.addEventListener(source, (e) => {
+        stream.push(JSON.parse(e.data));
+      });
+      eventSource.addEventListener("stream_stop", () => {
+        stream.close();
+      });
+      eventSource.addEventListener("reconnect", () => {
+        stream.close();
+      });
+      return [source, stream];
+    }),
+  );
+}
+
+window.connectSources = connectSources;
+window.apiEventSource = connectSources(sources, initialData);
 
 console.log(window.apiEventSource);
 console.log("preamble at", performance.now());